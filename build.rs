@@ -2,6 +2,7 @@ use anyhow::*;
 use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
 use std::env;
+use std::path::Path;
 
 fn main() -> Result<()> {
     println!("cargo:return-if-changed=res/*");
@@ -9,9 +10,30 @@ fn main() -> Result<()> {
     let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("res/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    // 只覆盖同名文件，之前用 `cargo run --bin bake` 生成的 .bake 会保留下来。
+    // 烘焙需要链接这个 crate 本身，所以不能在构建脚本里做；源文件变了时旧的烘焙文件会被忽略
+    let paths_to_copy = vec!["res/"];
+    copy_items(&paths_to_copy, &out_dir, &copy_options)?;
 
+    if env::var_os("CARGO_FEATURE_EMBED_RES").is_some() {
+        embed_res(&out_dir)?;
+    }
+
+    Ok(())
+}
+
+// 生成 `[(文件名, include_bytes!(...)), ...]`，由 vfs::Root::embedded 引入
+fn embed_res(out_dir: &str) -> Result<()> {
+    let res = Path::new(&env::var("CARGO_MANIFEST_DIR")?).join("res");
+    let mut entries = String::new();
+    for path in glob::glob(&format!("{}/**/*", res.display()))? {
+        let path = path?;
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.strip_prefix(&res)?.to_string_lossy().replace('\\', "/");
+        entries.push_str(&format!("    ({:?}, include_bytes!({:?}) as &'static [u8]),\n", name, path.display().to_string()));
+    }
+    std::fs::write(Path::new(out_dir).join("embedded_res.rs"), format!("[\n{}]\n", entries))?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,134 @@
+use std::{collections::HashMap, hash::Hash, ops::Deref, rc::{Rc, Weak}};
+
+use crate::{model::Model, texture::{Texture, TextureData}};
+
+// 引用计数的资源句柄，最后一个句柄释放时资源（包括 GPU 上的缓冲和贴图）一起释放
+pub struct Handle<T>(Rc<T>);
+
+impl<T> Handle<T> {
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Rc::ptr_eq(&a.0, &b.0)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+// 按 key 缓存还有句柄在用的资源，只保存弱引用，不会让资源一直活着
+struct Cache<K, T> {
+    entries: HashMap<K, Weak<T>>
+}
+
+impl<K: Eq + Hash, T> Cache<K, T> {
+    fn new() -> Self {
+        Self { entries: HashMap::new() }
+    }
+
+    fn get(&self, key: &K) -> Option<Handle<T>> {
+        self.entries.get(key)?.upgrade().map(Handle)
+    }
+
+    fn insert(&mut self, key: K, asset: T) -> Handle<T> {
+        // 顺便清掉已经释放的
+        self.entries.retain(|_, asset| asset.strong_count() > 0);
+        let asset = Rc::new(asset);
+        self.entries.insert(key, Rc::downgrade(&asset));
+        Handle(asset)
+    }
+
+    fn get_or_insert_with(&mut self, key: K, create: impl FnOnce() -> T) -> Handle<T> {
+        match self.get(&key) {
+            Some(handle) => handle,
+            None => self.insert(key, create())
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.values().filter(|asset| asset.strong_count() > 0).count()
+    }
+}
+
+// 按路径缓存模型和贴图。多个材质、多个模型用到同一张贴图时只上传一次
+pub struct Assets {
+    // 同一张图片作为法线贴图时不是 sRGB，要分开
+    textures: Cache<(String, bool), Texture>,
+    models: Cache<String, Model>
+}
+
+impl Assets {
+    pub fn new() -> Self {
+        Self { textures: Cache::new(), models: Cache::new() }
+    }
+
+    pub fn texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &TextureData) -> Handle<Texture> {
+        self.textures.get_or_insert_with((data.label.clone(), data.is_normal_map), || Texture::from_data(device, queue, data))
+    }
+
+    // 从 `path` 加载的模型还有句柄持有时返回它
+    pub fn model(&self, path: &str) -> Option<Handle<Model>> {
+        self.models.get(&path.to_owned())
+    }
+
+    pub fn insert_model(&mut self, path: &str, model: Model) -> Handle<Model> {
+        self.models.insert(path.to_owned(), model)
+    }
+
+    // 热加载时文件改过了，之后按这个路径取到的是重新上传的资源。还在用的旧资源不受影响
+    pub fn forget(&mut self, path: &str) {
+        self.textures.entries.retain(|(texture, _), _| texture != path);
+        self.models.entries.remove(path);
+    }
+
+    // 还在使用的模型和贴图数
+    pub fn len(&self) -> (usize, usize) {
+        (self.models.len(), self.textures.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, Handle};
+
+    #[test]
+    fn same_key_shares_the_asset() {
+        let mut cache = Cache::new();
+        let mut created = 0;
+        let a = cache.get_or_insert_with("a.png", || { created += 1; vec![1u8] });
+        let b = cache.get_or_insert_with("a.png", || { created += 1; vec![2u8] });
+        assert!(Handle::ptr_eq(&a, &b));
+        assert_eq!(*b, [1]);
+        assert_eq!(created, 1);
+        let c = cache.get_or_insert_with("c.png", || vec![3u8]);
+        assert!(!Handle::ptr_eq(&a, &c));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn assets_are_freed_with_their_last_handle() {
+        let mut cache = Cache::new();
+        let a = cache.insert("a.png", std::rc::Rc::new(()));
+        let freed = std::rc::Rc::downgrade(&*a);
+        let b = a.clone();
+        drop(a);
+        assert!(cache.get(&"a.png").is_some());
+        drop(b);
+        assert!(freed.upgrade().is_none());
+        assert!(cache.get(&"a.png").is_none());
+        assert_eq!(cache.len(), 0);
+        // 再次使用时重新创建
+        let mut created = false;
+        cache.get_or_insert_with("a.png", || { created = true; std::rc::Rc::new(()) });
+        assert!(created);
+    }
+}
@@ -0,0 +1,440 @@
+// 烘焙文件：把 OBJ/MTL 和贴图处理好的结果（可以直接上传的顶点、索引、材质参数和带 mipmap 的贴图）
+// 存成一个二进制文件，启动时不用再解析文本、生成切线和解码图片。
+// 文件放在源文件旁边，名字是 `<模型>.bake`，用 `cargo run --bin bake` 生成。
+//
+// 格式（小端）：
+//   magic "LWGPUBAK", VERSION: u32, 顶点大小: u32, 材质 uniform 大小: u32, MeshProcessing: u8
+//   源文件数: u32，每个是 名字, FNV-1a 哈希: u64
+//   贴图数: u32，每个是 名字, 宽: u32, 高: u32, 法线贴图: u8, mip 数: u32，每级是 RGBA8 数据
+//   材质数: u32，每个是 名字, uniform, alpha 模式: u8, 双面: u8, 漫反射贴图: u32, 法线贴图: u32
+//   网格数: u32，每个是 名字, 材质: u32, 索引格式: u8, 顶点数据, 索引数据
+// 字符串和数据块前面都有 u32 的长度。ModelVertex 和 MaterialUniform 只有 f32 字段，
+// 逐个按小端存，不依赖主机的字节序。
+use anyhow::{bail, Context};
+
+use crate::{
+    model::{AlphaMode, MaterialData, MaterialUniform, MeshData, ModelData, ModelVertex},
+    resources::{self, MeshProcessing},
+    texture::TextureData
+};
+
+const MAGIC: &[u8; 8] = b"LWGPUBAK";
+// 格式或处理流程变化时加一，旧的烘焙文件会被忽略
+const VERSION: u32 = 3;
+
+pub fn baked_name(file_name: &str) -> String {
+    format!("{}.bake", file_name)
+}
+
+// 从源文件加载 `file_name` 并烘焙，返回文件内容
+pub async fn bake(file_name: &str, processing: &MeshProcessing) -> crate::error::Result<Vec<u8>> {
+    let mut sources: Vec<(String, u64)> = Vec::new();
+    let model = resources::load_model_data(file_name, processing, &mut |name, data| {
+        if !sources.iter().any(|(n, _)| n == name) {
+            sources.push((name.to_owned(), fnv1a(data)));
+        }
+    }).await?;
+    Ok(write(&model, processing, &sources))
+}
+
+// 读取 `file_name` 的烘焙文件。没有烘焙文件、版本或加载参数不一致、源文件变过时返回 None。
+// 读到的文件同样报告给 `on_file`
+pub async fn load(
+    file_name: &str,
+    processing: &MeshProcessing,
+    on_file: &mut dyn FnMut(&str, &[u8])
+) -> Option<ModelData> {
+    let baked = baked_name(file_name);
+    let bytes = resources::load_binary(&baked).await.ok()?;
+    on_file(&baked, &bytes);
+    let (model, sources) = match read(&bytes, processing) {
+        Ok(result) => result,
+        Err(e) => {
+            log::info!("{}: {:#}, loading {} instead", baked, e, file_name);
+            return None;
+        }
+    };
+    // Web 上为了检查再下载一遍源文件就失去了烘焙的意义
+    if cfg!(not(target_arch = "wasm32")) {
+        for (name, hash) in &sources {
+            let current = resources::load_binary(name).await.ok().map(|data| {
+                on_file(name, &data);
+                fnv1a(&data)
+            });
+            if current != Some(*hash) {
+                log::info!("{} changed since {} was baked, loading {} instead", name, baked, file_name);
+                return None;
+            }
+        }
+    }
+    Some(model)
+}
+
+fn processing_flags(processing: &MeshProcessing) -> u8 {
+    processing.weld as u8 | (processing.optimize as u8) << 1 | (processing.compact_indices as u8) << 2
+}
+
+// 64 位 FNV-1a，结果不随 Rust 版本变化
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn write(model: &ModelData, processing: &MeshProcessing, sources: &[(String, u64)]) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.0.extend_from_slice(MAGIC);
+    w.u32(VERSION);
+    w.u32(std::mem::size_of::<ModelVertex>() as u32);
+    w.u32(std::mem::size_of::<MaterialUniform>() as u32);
+    w.u8(processing_flags(processing));
+
+    w.u32(sources.len() as u32);
+    for (name, hash) in sources {
+        w.str(name);
+        w.u64(*hash);
+    }
+
+    w.u32(model.textures.len() as u32);
+    for texture in &model.textures {
+        w.texture(texture);
+    }
+
+    w.u32(model.materials.len() as u32);
+    for m in &model.materials {
+        w.str(&m.name);
+        w.f32s(bytemuck::cast_slice(std::slice::from_ref(&m.uniform)));
+        w.u8(match m.alpha_mode {
+            AlphaMode::Opaque => 0,
+            AlphaMode::Mask => 1,
+            AlphaMode::Blend => 2
+        });
+        w.u8(m.double_sided as u8);
+        w.u32(m.diffuse_texture as u32);
+        w.u32(m.normal_texture as u32);
+    }
+
+    w.u32(model.meshes.len() as u32);
+    for m in &model.meshes {
+        w.str(&m.name);
+        w.u32(m.material as u32);
+        w.u8(match m.index_format {
+            wgpu::IndexFormat::Uint16 => 0,
+            wgpu::IndexFormat::Uint32 => 1
+        });
+        let vertices: &[f32] = bytemuck::cast_slice(&m.vertices);
+        w.u32(std::mem::size_of_val(vertices) as u32);
+        w.f32s(vertices);
+        w.bytes(&m.index_bytes());
+    }
+    w.0
+}
+
+fn read(bytes: &[u8], processing: &MeshProcessing) -> anyhow::Result<(ModelData, Vec<(String, u64)>)> {
+    let mut r = Reader(bytes);
+    if r.take(MAGIC.len())? != MAGIC {
+        bail!("not a baked model");
+    }
+    let version = r.u32()?;
+    if version != VERSION {
+        bail!("baked with format version {}, expected {}", version, VERSION);
+    }
+    if r.u32()? as usize != std::mem::size_of::<ModelVertex>() || r.u32()? as usize != std::mem::size_of::<MaterialUniform>() {
+        bail!("vertex or material layout changed");
+    }
+    if r.u8()? != processing_flags(processing) {
+        bail!("baked with different mesh processing");
+    }
+
+    let sources = (0..r.u32()?)
+        .map(|_| Ok((r.str()?, r.u64()?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let textures = (0..r.u32()?)
+        .map(|_| r.texture())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut materials = Vec::new();
+    for _ in 0..r.u32()? {
+        let name = r.str()?;
+        let uniform = bytemuck::pod_read_unaligned(bytemuck::cast_slice(&le_f32s(r.take(std::mem::size_of::<MaterialUniform>())?)));
+        let alpha_mode = match r.u8()? {
+            0 => AlphaMode::Opaque,
+            1 => AlphaMode::Mask,
+            2 => AlphaMode::Blend,
+            other => bail!("{}: unknown alpha mode {}", name, other)
+        };
+        let double_sided = r.u8()? != 0;
+        let (diffuse_texture, normal_texture) = (r.u32()? as usize, r.u32()? as usize);
+        if diffuse_texture >= textures.len() || normal_texture >= textures.len() {
+            bail!("{}: texture out of range", name);
+        }
+        materials.push(MaterialData { name, diffuse_texture, normal_texture, uniform, alpha_mode, double_sided });
+    }
+
+    let mut meshes = Vec::new();
+    for _ in 0..r.u32()? {
+        let name = r.str()?;
+        let material = r.u32()? as usize;
+        if material >= materials.len() {
+            bail!("{}: material {} out of range", name, material);
+        }
+        let index_format = match r.u8()? {
+            0 => wgpu::IndexFormat::Uint16,
+            1 => wgpu::IndexFormat::Uint32,
+            other => bail!("{}: unknown index format {}", name, other)
+        };
+        let vertices = r.bytes()?;
+        if vertices.len() % std::mem::size_of::<ModelVertex>() != 0 {
+            bail!("{}: truncated vertex data", name);
+        }
+        let vertices: Vec<ModelVertex> = bytemuck::pod_collect_to_vec(&le_f32s(vertices));
+        let indices = r.bytes()?;
+        let indices: Vec<u32> = match index_format {
+            wgpu::IndexFormat::Uint16 => indices.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32).collect(),
+            wgpu::IndexFormat::Uint32 => indices.chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+        };
+        if indices.iter().any(|&i| i as usize >= vertices.len()) {
+            bail!("{}: index out of range", name);
+        }
+        meshes.push(MeshData { name, vertices, indices, index_format, material });
+    }
+    if !r.0.is_empty() {
+        bail!("{} trailing bytes", r.0.len());
+    }
+    Ok((ModelData { meshes, materials, textures }, sources))
+}
+
+fn le_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32s(&mut self, data: &[f32]) {
+        for v in data {
+            self.0.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.0.extend_from_slice(data);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn texture(&mut self, texture: &TextureData) {
+        self.str(&texture.label);
+        self.u32(texture.width);
+        self.u32(texture.height);
+        self.u8(texture.is_normal_map as u8);
+        self.u32(texture.mips.len() as u32);
+        for mip in &texture.mips {
+            self.bytes(mip);
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("unexpected end of file");
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> anyhow::Result<String> {
+        Ok(std::str::from_utf8(self.bytes()?).context("invalid name")?.to_owned())
+    }
+
+    fn texture(&mut self) -> anyhow::Result<TextureData> {
+        let label = self.str()?;
+        let (width, height) = (self.u32()?, self.u32()?);
+        let is_normal_map = self.u8()? != 0;
+        if width == 0 || height == 0 {
+            bail!("{}: empty texture", label);
+        }
+        let mut texture = TextureData { label, width, height, is_normal_map, mips: Vec::new() };
+        // log2(max(w, h)) + 1 级，最后一级是 1x1
+        let max_levels = u32::BITS - width.max(height).leading_zeros();
+        let levels = self.u32()?;
+        if levels > max_levels {
+            bail!("{}: {} mip levels, a {}x{} texture has at most {}", texture.label, levels, width, height, max_levels);
+        }
+        for level in 0..levels as usize {
+            let (w, h) = texture.mip_size(level);
+            let size = (w as usize).checked_mul(h as usize).and_then(|n| n.checked_mul(4));
+            let mip = self.bytes()?;
+            if size != Some(mip.len()) {
+                bail!("{}: mip {} has the wrong size", texture.label, level);
+            }
+            texture.mips.push(mip.to_vec());
+        }
+        if texture.mips.is_empty() {
+            bail!("{}: no mip levels", texture.label);
+        }
+        Ok(texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, write, MeshProcessing, Writer};
+    use crate::{
+        model::{AlphaMode, MaterialData, MaterialUniform, MeshData, ModelData, ModelVertex},
+        texture::TextureData
+    };
+
+    fn texture(label: &str, is_normal_map: bool) -> TextureData {
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(5, 3, |x, y| image::Rgba([x as u8 * 50, y as u8 * 80, 0, 255])));
+        TextureData::from_image(&image, label, is_normal_map)
+    }
+
+    fn model(index_format: wgpu::IndexFormat) -> ModelData {
+        let vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .map(|position| ModelVertex { position, ..Default::default() })
+            .to_vec();
+        ModelData {
+            meshes: vec![MeshData { name: "quad".to_owned(), vertices, indices: vec![0, 1, 2, 2, 1, 0], index_format, material: 1 }],
+            materials: vec![
+                MaterialData {
+                    name: "a".to_owned(),
+                    diffuse_texture: 0,
+                    normal_texture: 1,
+                    uniform: MaterialUniform::default(),
+                    alpha_mode: AlphaMode::Opaque,
+                    double_sided: false
+                },
+                MaterialData {
+                    name: "b".to_owned(),
+                    diffuse_texture: 2,
+                    normal_texture: 1,
+                    uniform: MaterialUniform { diffuse: [0.5, 0.25, 1.0, 0.75], emissive: [1.0, 0.0, 0.0], alpha_cutoff: 0.5 },
+                    alpha_mode: AlphaMode::Mask,
+                    double_sided: true
+                }
+            ],
+            textures: vec![texture("a.png", false), texture("normal.png", true), texture("b.png", false)]
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let processing = MeshProcessing::default();
+        let sources = vec![("a.obj".to_owned(), 1), ("a.png".to_owned(), u64::MAX)];
+        for index_format in [wgpu::IndexFormat::Uint16, wgpu::IndexFormat::Uint32] {
+            let model = model(index_format);
+            let (read_model, read_sources) = read(&write(&model, &processing, &sources), &processing).unwrap();
+            assert_eq!(read_model, model);
+            assert_eq!(read_sources, sources);
+        }
+    }
+
+    #[test]
+    fn mip_chain_ends_at_one_pixel() {
+        let mips = texture("a.png", false).mips;
+        let sizes = mips.iter().map(|m| m.len() / 4).collect::<Vec<_>>();
+        assert_eq!(sizes, [15, 2, 1]);
+    }
+
+    #[test]
+    fn rejects_mismatched_processing() {
+        let baked = write(&model(wgpu::IndexFormat::Uint32), &MeshProcessing::default(), &[]);
+        let processing = MeshProcessing { optimize: false, ..Default::default() };
+        assert!(read(&baked, &processing).is_err());
+    }
+
+    #[test]
+    fn rejects_other_versions_and_truncated_files() {
+        let processing = MeshProcessing::default();
+        let mut baked = write(&model(wgpu::IndexFormat::Uint32), &processing, &[]);
+        for len in [0, 8, 12, baked.len() / 2, baked.len() - 1] {
+            assert!(read(&baked[..len], &processing).is_err(), "length {}", len);
+        }
+        baked[8] += 1;
+        let e = read(&baked, &processing).unwrap_err();
+        assert!(e.to_string().contains("version"), "{}", e);
+    }
+
+    #[test]
+    fn rejects_materials_out_of_range() {
+        let processing = MeshProcessing::default();
+        let mut model = model(wgpu::IndexFormat::Uint16);
+        model.meshes[0].material = 2;
+        let e = read(&write(&model, &processing, &[]), &processing).unwrap_err();
+        assert!(e.to_string().contains("material 2 out of range"), "{}", e);
+    }
+
+    // 只有贴图部分的 Reader 输入
+    fn texture_bytes(width: u32, height: u32, mips: &[Vec<u8>]) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.texture(&TextureData { label: "t.png".to_owned(), width, height, is_normal_map: false, mips: mips.to_vec() });
+        w.0
+    }
+
+    #[test]
+    fn rejects_impossible_mip_chains() {
+        let read_texture = |bytes: &[u8]| super::Reader(bytes).texture();
+        assert_eq!(read_texture(&texture_bytes(4, 1, &[vec![0; 16], vec![0; 8], vec![0; 4]])).unwrap().mips.len(), 3);
+        let e = read_texture(&texture_bytes(4, 1, &[vec![0; 16], vec![0; 8], vec![0; 4], vec![0; 4]])).unwrap_err();
+        assert!(e.to_string().contains("at most 3"), "{}", e);
+        // 级数大到移位会溢出
+        let e = read_texture(&texture_bytes(1, 1, &vec![vec![0; 4]; 40])).unwrap_err();
+        assert!(e.to_string().contains("at most 1"), "{}", e);
+        let e = read_texture(&texture_bytes(u32::MAX, u32::MAX, &[vec![0; 4]])).unwrap_err();
+        assert!(e.to_string().contains("wrong size"), "{}", e);
+        assert!(read_texture(&texture_bytes(0, 4, &[])).is_err());
+        let texture = TextureData::solid("t.png", [0; 4], false);
+        assert_eq!(texture.mip_size(32), (1, 1));
+        assert_eq!(TextureData { width: u32::MAX, ..texture }.mip_size(31), (1, 1));
+    }
+
+    #[test]
+    fn floats_are_little_endian() {
+        let processing = MeshProcessing::default();
+        let mut model = model(wgpu::IndexFormat::Uint16);
+        model.meshes[0].vertices[0].position = [1.5, -2.0, 1e-3];
+        model.materials[0].uniform.diffuse = [0.125, 8.0, -1.0, 3.25];
+        let baked = write(&model, &processing, &[]);
+        let le = |values: &[f32]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        for expected in [le(&[1.5, -2.0, 1e-3]), le(&[0.125, 8.0, -1.0, 3.25])] {
+            assert!(baked.windows(expected.len()).any(|w| w == expected));
+        }
+        assert_eq!(read(&baked, &processing).unwrap().0, model);
+    }
+}
@@ -0,0 +1,42 @@
+// 把 res 里的模型烘焙成 `<模型>.bake`，运行时 resources::read_model 会优先读取它。
+// 用法：cargo run --bin bake [模型文件名...]，不给文件名时烘焙所有 .obj、.ply 和 .stl。
+// 读写的都是 build.rs 复制到 OUT_DIR/res 的那份资源，debug 和 release 的 OUT_DIR 不同，
+// 要用和运行程序时相同的 profile。
+use std::path::Path;
+
+use learn_wgpu::{bake, baked_name, MeshProcessing};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let res = Path::new(env!("OUT_DIR")).join("res");
+    let mut models = std::env::args().skip(1).collect::<Vec<_>>();
+    if models.is_empty() {
+        for entry in std::fs::read_dir(&res)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if [".obj", ".ply", ".stl"].iter().any(|ext| name.ends_with(ext)) {
+                models.push(name);
+            }
+        }
+        models.sort();
+    }
+
+    let processing = MeshProcessing::default();
+    let mut failed = 0;
+    for model in &models {
+        match pollster::block_on(bake(model, &processing)) {
+            Ok(baked) => {
+                let path = res.join(baked_name(model));
+                std::fs::write(&path, &baked)?;
+                println!("{} -> {} ({} KiB)", model, path.display(), baked.len() / 1024);
+            }
+            Err(e) => {
+                eprintln!("{}", e.report());
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} models failed to bake", failed, models.len());
+    }
+    Ok(())
+}
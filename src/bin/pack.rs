@@ -0,0 +1,41 @@
+// 把资源目录打包成一个 `res.pack`，运行时 vfs 会在程序旁边找到它。
+// 用法：cargo run --bin pack [资源目录] [输出文件]
+// 默认打包 build.rs 复制到 OUT_DIR/res 的资源（包括烘焙文件），输出到可执行文件所在的目录。
+use std::path::{Path, PathBuf};
+
+use learn_wgpu::write_pack;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let res = args.next().map(PathBuf::from).unwrap_or_else(|| Path::new(env!("OUT_DIR")).join("res"));
+    let output = match args.next() {
+        Some(output) => PathBuf::from(output),
+        None => std::env::current_exe()?.with_file_name("res.pack")
+    };
+
+    let mut files = Vec::new();
+    collect(&res, &res, &mut files)?;
+    // 顺序固定，同样的资源生成同样的包
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    let pack = write_pack(&files);
+    std::fs::write(&output, &pack)?;
+    println!("{} files -> {} ({} KiB)", files.len(), output.display(), pack.len() / 1024);
+    Ok(())
+}
+
+// 包里的名字是相对资源目录、用 / 分隔的路径，和 load_binary 的参数一样
+fn collect(root: &Path, dir: &Path, files: &mut Vec<(String, Vec<u8>)>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(root, &path, files)?;
+        } else {
+            let name = path.strip_prefix(root)?.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((name, std::fs::read(&path)?));
+        }
+    }
+    Ok(())
+}
@@ -1,5 +1,5 @@
-use cgmath::{InnerSpace, Rad};
-use crate::global::OPENGL_TO_WGPU_MATRIX; 
+use cgmath::{InnerSpace, Rad, SquareMatrix};
+use crate::{global::OPENGL_TO_WGPU_MATRIX, ray::Ray}; 
 
 #[derive(Debug)]
 pub struct Camera {
@@ -25,6 +25,25 @@ impl Camera {
             cgmath::Vector3::unit_y()
         )
     }
+
+    // (x, y) 是窗口坐标，原点在左上角
+    pub fn screen_to_ray(
+        &self,
+        x: f32,
+        y: f32,
+        size: winit::dpi::PhysicalSize<u32>,
+        projection: &Projection
+    ) -> Ray {
+        let ndc_x = x / size.width as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - y / size.height as f32 * 2.0;
+        let inv_view_proj = (projection.calc_matrix() * self.calc_matrix())
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        // wgpu 的 NDC 深度范围是 0..1
+        let near = cgmath::Point3::from_homogeneous(inv_view_proj * cgmath::Vector4::new(ndc_x, ndc_y, 0.0, 1.0));
+        let far = cgmath::Point3::from_homogeneous(inv_view_proj * cgmath::Vector4::new(ndc_x, ndc_y, 1.0, 1.0));
+        Ray::new(near, (far - near).normalize())
+    }
 }
 
 pub struct Projection {
@@ -56,6 +75,11 @@ impl Projection {
     pub fn calc_matrix(&self) -> cgmath::Matrix4<f32> {
         OPENGL_TO_WGPU_MATRIX * cgmath::perspective(self.fovy, self.aspect, self.znear, self.zfar)
     }
+
+    // (znear, zfar)
+    pub fn clip_planes(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
 }
 
 #[repr(C)]
@@ -67,7 +91,6 @@ pub struct CameraUniform {
 
 impl CameraUniform {
     pub fn new() -> Self {
-        use cgmath::SquareMatrix;
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into() 
@@ -78,4 +101,46 @@ impl CameraUniform {
         self.view_position = camera.position.to_homogeneous().into();
         self.view_proj = (projection.calc_matrix()*camera.calc_matrix()).into();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Deg;
+
+    use super::{Camera, CameraUniform, Projection};
+    use crate::shaders::reflect::{gpu_fields, ShaderReflection};
+
+    // debug.wgsl 和 picking.wgsl 还有各自的 Camera
+    #[test]
+    fn uniform_matches_wgsl() {
+        let fields = gpu_fields!(CameraUniform: view_position, view_proj);
+        ShaderReflection::compose("shaders/camera.wgsl", &[("CAMERA_GROUP", "0")])
+            .check_uniform::<CameraUniform>("camera", &fields);
+        ShaderReflection::parse("debug/debug.wgsl", include_str!("debug/debug.wgsl"))
+            .check_uniform::<CameraUniform>("camera", &fields);
+        ShaderReflection::parse("picking/picking.wgsl", include_str!("picking/picking.wgsl"))
+            .check_uniform::<CameraUniform>("camera", &fields);
+    }
+
+    #[test]
+    fn screen_to_ray_projects_back_onto_the_pixel() {
+        let size = winit::dpi::PhysicalSize::new(800, 600);
+        let camera = Camera::new((1.0, 2.0, 5.0), Deg(-100.0), Deg(-20.0));
+        let projection = Projection::new(size.width, size.height, Deg(45.0), 0.1, 100.0);
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        for (x, y) in [(0.0, 0.0), (400.0, 300.0), (799.5, 0.5), (123.25, 456.75)] {
+            let ray = camera.screen_to_ray(x, y, size, &projection);
+            for t in [0.0, 1.0, 50.0] {
+                let clip = view_proj * ray.at(t).to_homogeneous();
+                let ndc = clip.truncate() / clip.w;
+                let pixel = ((ndc.x + 1.0) * 0.5 * size.width as f32, (1.0 - ndc.y) * 0.5 * size.height as f32);
+                assert!(
+                    (pixel.0 - x).abs() < 0.01 && (pixel.1 - y).abs() < 0.01,
+                    "({}, {}) at t = {} projected to {:?}", x, y, t, pixel
+                );
+                // 起点在近平面上，之后都在视锥里
+                assert!(ndc.z >= -1e-4 && ndc.z <= 1.0, "depth {} at t = {}", ndc.z, t);
+            }
+        }
+    }
+}
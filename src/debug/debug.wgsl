@@ -0,0 +1,236 @@
+// 相机
+struct Camera {
+    view_pos: vec4f,
+    view_proj: mat4x4f
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Debug {
+    mode: u32,
+    // 法线、切线箭头在世界空间中的长度
+    line_length: f32,
+    znear: f32,
+    zfar: f32
+}
+@group(1) @binding(0)
+var<uniform> debug: Debug;
+
+// 与 DebugLineVertex.kind 一致
+const VECTOR_NORMAL: u32 = 0u;
+const VECTOR_TANGENT: u32 = 1u;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4f,
+    @location(6) model_matrix_1: vec4f,
+    @location(7) model_matrix_2: vec4f,
+    @location(8) model_matrix_3: vec4f,
+    @location(9) normal_matrix_0: vec3f,
+    @location(10) normal_matrix_1: vec3f,
+    @location(11) normal_matrix_2: vec3f,
+}
+
+fn instance_model_matrix(instance: InstanceInput) -> mat4x4f {
+    return mat4x4f(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+fn instance_normal_matrix(instance: InstanceInput) -> mat3x3f {
+    return mat3x3f(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+}
+
+// 零向量归一化会得到 NaN，调试时宁可显示黑色
+fn safe_normalize(v: vec3f) -> vec3f {
+    let len = length(v);
+    if (len > 0.0) {
+        return v / len;
+    }
+    return vec3f(0.0);
+}
+
+// 调试视图
+
+struct VertexInput {
+    @location(0) position: vec3f,
+    @location(1) tex_coords: vec2f,
+    @location(2) normal: vec3f,
+    @location(3) tangent: vec4f,
+}
+
+// 与 shader.wgsl 相同，见 vertex::PackedVertexAttributes
+struct PackedVertexInput {
+    @location(0) position: vec3f,
+    @location(1) tex_coords: vec2f,
+    @location(2) normal: vec2f,
+    @location(3) tangent: vec4f,
+}
+
+fn octahedral_decode(e: vec2f) -> vec3f {
+    var n = vec3f(e, 1.0 - abs(e.x) - abs(e.y));
+    if (n.z < 0.0) {
+        n = vec3f((1.0 - abs(n.yx)) * select(vec2f(-1.0), vec2f(1.0), n.xy >= vec2f(0.0)), n.z);
+    }
+    return normalize(n);
+}
+
+struct ViewOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) tex_coords: vec2f,
+    @location(1) normal: vec3f,
+    @location(2) tangent: vec3f,
+    @location(3) bitangent: vec3f,
+}
+
+@vertex
+fn vs_view(model: VertexInput, instance: InstanceInput) -> ViewOutput {
+    return view_vertex(model, instance);
+}
+
+@vertex
+fn vs_view_packed(model: PackedVertexInput, instance: InstanceInput) -> ViewOutput {
+    return view_vertex(VertexInput(model.position, model.tex_coords, octahedral_decode(model.normal), model.tangent), instance);
+}
+
+fn view_vertex(model: VertexInput, instance: InstanceInput) -> ViewOutput {
+    let model_matrix = instance_model_matrix(instance);
+    var out: ViewOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4f(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    // 与 shader.wgsl 中的变换方式相同
+    out.normal = instance_normal_matrix(instance) * model.normal;
+    out.tangent = (model_matrix * vec4f(model.tangent.xyz, 0.0)).xyz;
+    out.bitangent = cross(out.normal, out.tangent) * model.tangent.w;
+    return out;
+}
+
+@fragment
+fn fs_view(in: ViewOutput) -> @location(0) vec4f {
+    // 与 DebugMode::shader_mode 一致，case 只能写字面量
+    switch debug.mode {
+        // 法线
+        case 1u: {
+            return vec4f(safe_normalize(in.normal) * 0.5 + 0.5, 1.0);
+        }
+        // 切线
+        case 2u: {
+            return vec4f(safe_normalize(in.tangent) * 0.5 + 0.5, 1.0);
+        }
+        // 副切线
+        case 3u: {
+            return vec4f(safe_normalize(in.bitangent) * 0.5 + 0.5, 1.0);
+        }
+        // 纹理坐标
+        case 4u: {
+            // 与 fs_main 一样翻转 Y，显示的是实际用来采样的坐标
+            let uv = vec2f(in.tex_coords.x, 1. - in.tex_coords.y);
+            return vec4f(fract(uv), 0.0, 1.0);
+        }
+        // 深度
+        case 5u: {
+            // 还原线性深度，近处黑远处白
+            let z = in.clip_position.z;
+            let view_depth = debug.znear * debug.zfar / (debug.zfar - z * (debug.zfar - debug.znear));
+            let d = (view_depth - debug.znear) / (debug.zfar - debug.znear);
+            return vec4f(vec3f(d), 1.0);
+        }
+        default: {
+            return vec4f(1.0, 0.0, 1.0, 1.0);
+        }
+    }
+}
+
+// 线框
+
+const WIRE_COLOR: vec4f = vec4f(0.0, 1.0, 0.3, 1.0);
+
+@vertex
+fn vs_wire(@location(0) position: vec3f, instance: InstanceInput) -> @builtin(position) vec4f {
+    return camera.view_proj * instance_model_matrix(instance) * vec4f(position, 1.0);
+}
+
+@fragment
+fn fs_wire() -> @location(0) vec4f {
+    return WIRE_COLOR;
+}
+
+// 没有 POLYGON_MODE_LINE 时，按非索引的三角形绘制，用重心坐标找出靠近边的片元
+
+struct WireOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) barycentric: vec3f,
+}
+
+@vertex
+fn vs_wire_barycentric(
+    @location(0) position: vec3f,
+    instance: InstanceInput,
+    @builtin(vertex_index) vertex_index: u32
+) -> WireOutput {
+    var out: WireOutput;
+    out.clip_position = camera.view_proj * instance_model_matrix(instance) * vec4f(position, 1.0);
+    let corner = vertex_index % 3u;
+    out.barycentric = vec3f(f32(corner == 0u), f32(corner == 1u), f32(corner == 2u));
+    return out;
+}
+
+@fragment
+fn fs_wire_barycentric(in: WireOutput) -> @location(0) vec4f {
+    // 线宽约 1 个像素
+    let width = fwidth(in.barycentric);
+    let edge = smoothstep(vec3f(0.0), width * 1.5, in.barycentric);
+    let coverage = 1.0 - min(min(edge.x, edge.y), edge.z);
+    if (coverage < 0.5) {
+        discard;
+    }
+    return WIRE_COLOR;
+}
+
+// 法线、切线和副切线向量
+
+struct VectorInput {
+    @location(0) position: vec3f,
+    @location(1) direction: vec3f,
+    // 0 是起点，1 是终点
+    @location(2) along: f32,
+    @location(3) kind: u32,
+}
+
+struct VectorOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) color: vec3f,
+}
+
+@vertex
+fn vs_vector(line: VectorInput, instance: InstanceInput) -> VectorOutput {
+    let model_matrix = instance_model_matrix(instance);
+    let origin = model_matrix * vec4f(line.position, 1.0);
+    var direction: vec3f;
+    var out: VectorOutput;
+    if (line.kind == VECTOR_NORMAL) {
+        direction = instance_normal_matrix(instance) * line.direction;
+        out.color = vec3f(0.0, 0.0, 1.0);
+    } else {
+        direction = (model_matrix * vec4f(line.direction, 0.0)).xyz;
+        if (line.kind == VECTOR_TANGENT) {
+            out.color = vec3f(1.0, 0.0, 0.0);
+        } else {
+            out.color = vec3f(0.0, 1.0, 0.0);
+        }
+    }
+    let position = origin.xyz + safe_normalize(direction) * debug.line_length * line.along;
+    out.clip_position = camera.view_proj * vec4f(position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_vector(in: VectorOutput) -> @location(0) vec4f {
+    return vec4f(in.color, 1.0);
+}
@@ -0,0 +1,369 @@
+use std::{ops::Range, rc::Rc};
+
+use cgmath::Vector3;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::Projection,
+    instance::InstanceRaw,
+    model::Model,
+    pipeline::{PipelineBuilder, PipelineCache},
+    texture::Texture,
+    vertex::{PositionVertex, Vertex, VertexLayout}
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugMode {
+    Shaded,
+    // 正常着色，再叠加网格线
+    Wireframe,
+    Normals,
+    Tangents,
+    Bitangents,
+    TexCoords,
+    Depth
+}
+
+impl DebugMode {
+    pub fn next(self) -> Self {
+        match self {
+            DebugMode::Shaded => DebugMode::Wireframe,
+            DebugMode::Wireframe => DebugMode::Normals,
+            DebugMode::Normals => DebugMode::Tangents,
+            DebugMode::Tangents => DebugMode::Bitangents,
+            DebugMode::Bitangents => DebugMode::TexCoords,
+            DebugMode::TexCoords => DebugMode::Depth,
+            DebugMode::Depth => DebugMode::Shaded
+        }
+    }
+
+    // debug.wgsl 中 `Debug.mode` 的值，正常着色时为 None
+    fn shader_mode(self) -> Option<u32> {
+        match self {
+            DebugMode::Shaded | DebugMode::Wireframe => None,
+            DebugMode::Normals => Some(1),
+            DebugMode::Tangents => Some(2),
+            DebugMode::Bitangents => Some(3),
+            DebugMode::TexCoords => Some(4),
+            DebugMode::Depth => Some(5)
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugUniform {
+    mode: u32,
+    line_length: f32,
+    znear: f32,
+    zfar: f32
+}
+
+// 法线、切线、副切线箭头的一个端点
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugLineVertex {
+    position: [f32; 3],
+    direction: [f32; 3],
+    along: f32,
+    // 0 法线，1 切线，2 副切线
+    kind: u32
+}
+
+impl DebugLineVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 4] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32, 3 => Uint32];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugLineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS
+        }
+    }
+}
+
+// 箭头长度相对于模型包围球半径
+const LINE_LENGTH_SCALE: f32 = 0.1;
+
+// 画调试视图、叠加的线框和向量。
+// 管线和每个网格的缓冲在某个模式第一次用到时才创建
+pub struct DebugRenderer {
+    mode: DebugMode,
+    show_vectors: bool,
+    // 设备支持 PolygonMode::Line，否则用重心坐标画线框
+    line_mode: bool,
+    color_format: wgpu::TextureFormat,
+    multisample: wgpu::MultisampleState,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    layout: wgpu::PipelineLayout,
+    // 按模型的顶点格式创建，格式变了重新创建
+    view_pipeline: Option<(VertexLayout, Rc<wgpu::RenderPipeline>)>,
+    wire_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    vector_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    // 按 model.meshes 的下标，(buffer, 顶点数)
+    wire_buffers: Vec<(wgpu::Buffer, u32)>,
+    vector_buffers: Vec<(wgpu::Buffer, u32)>
+}
+
+impl DebugRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        multisample: wgpu::MultisampleState
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("debug_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None
+                },
+                count: None
+            }]
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Debug Buffer"),
+            contents: bytemuck::cast_slice(&[DebugUniform { mode: 0, line_length: 0.0, znear: 0.0, zfar: 0.0 }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("debug_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding()
+            }]
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        Self {
+            mode: DebugMode::Shaded,
+            show_vectors: false,
+            line_mode: device.features().contains(wgpu::Features::POLYGON_MODE_LINE),
+            color_format,
+            multisample,
+            uniform_buffer,
+            bind_group,
+            layout,
+            view_pipeline: None,
+            wire_pipeline: None,
+            vector_pipeline: None,
+            wire_buffers: Vec::new(),
+            vector_buffers: Vec::new()
+        }
+    }
+
+    pub fn mode(&self) -> DebugMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: DebugMode) {
+        self.mode = mode;
+    }
+
+    pub fn toggle_vectors(&mut self) -> bool {
+        self.show_vectors = !self.show_vectors;
+        self.show_vectors
+    }
+
+    // 丢掉为上一个模型生成的叠加缓冲，比如加载完的模型替换了占位模型
+    pub fn model_changed(&mut self) {
+        self.wire_buffers.clear();
+        self.vector_buffers.clear();
+    }
+
+    // 多重采样数变了以后，下次 prepare 用新的设置重建管线
+    pub fn set_multisample(&mut self, multisample: wgpu::MultisampleState) {
+        self.multisample = multisample;
+        self.shader_changed();
+    }
+
+    // 丢掉管线，下次 `prepare` 用传入的源码重建
+    pub fn shader_changed(&mut self) {
+        self.view_pipeline = None;
+        self.wire_pipeline = None;
+        self.vector_pipeline = None;
+    }
+
+    // 调试视图代替模型的正常着色时为 true
+    pub fn replaces_shading(&self) -> bool {
+        self.mode.shader_mode().is_some()
+    }
+
+    // 创建当前模式需要的资源并更新 uniform，在 `draw` 之前调用。
+    // `shader` 是预处理后的 debug.wgsl
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &mut PipelineCache,
+        model: &Model,
+        projection: &Projection,
+        shader: &str
+    ) {
+        let (color_format, multisample) = (self.color_format, self.multisample);
+        let (znear, zfar) = projection.clip_planes();
+        let uniform = DebugUniform {
+            mode: self.mode.shader_mode().unwrap_or(0),
+            line_length: model.bounds().map(|b| b.radius()).unwrap_or(1.0) * LINE_LENGTH_SCALE,
+            znear,
+            zfar
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let instance_layout = InstanceRaw::desc();
+        let vertex_layout = model.vertex_layout;
+        if self.replaces_shading() && self.view_pipeline.as_ref().is_none_or(|(layout, _)| *layout != vertex_layout) {
+            let vs_entry = match vertex_layout {
+                VertexLayout::Full => "vs_view",
+                VertexLayout::Packed => "vs_view_packed"
+            };
+            self.view_pipeline = Some((vertex_layout,
+                PipelineBuilder::new("Debug View Pipeline", &self.layout, shader, color_format)
+                    .entry_points(vs_entry, "fs_view")
+                    .vertex_layouts(&[PositionVertex::desc(), instance_layout.clone(), vertex_layout.desc()])
+                    .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+                    .multisample(multisample)
+                    .build(device, pipelines)
+            ));
+        }
+        if self.mode == DebugMode::Wireframe && self.wire_pipeline.is_none() {
+            // 叠加在已经画好的模型上，不写深度
+            let builder = PipelineBuilder::new("Debug Wire Pipeline", &self.layout, shader, color_format)
+                .depth(Texture::DEPTH_FORMAT, false, wgpu::CompareFunction::LessEqual)
+                .multisample(multisample);
+            let builder = if self.line_mode {
+                builder
+                    .entry_points("vs_wire", "fs_wire")
+                    .vertex_layouts(&[PositionVertex::desc(), instance_layout.clone()])
+                    .polygon_mode(wgpu::PolygonMode::Line)
+            } else {
+                builder
+                    .entry_points("vs_wire_barycentric", "fs_wire_barycentric")
+                    .vertex_layouts(&[PositionVertex::desc(), instance_layout.clone()])
+            };
+            self.wire_pipeline = Some(builder.build(device, pipelines));
+        }
+        if self.show_vectors && self.vector_pipeline.is_none() {
+            self.vector_pipeline = Some(
+                PipelineBuilder::new("Debug Vector Pipeline", &self.layout, shader, color_format)
+                    .entry_points("vs_vector", "fs_vector")
+                    .vertex_layouts(&[DebugLineVertex::desc(), instance_layout])
+                    .topology(wgpu::PrimitiveTopology::LineList)
+                    .cull_mode(None)
+                    .depth(Texture::DEPTH_FORMAT, false, wgpu::CompareFunction::LessEqual)
+                    .multisample(multisample)
+                    .build(device, pipelines)
+            );
+        }
+
+        // 模型换了以后重新生成
+        if self.mode == DebugMode::Wireframe && !self.line_mode && self.wire_buffers.len() != model.meshes.len() {
+            self.wire_buffers = model.meshes.iter().map(|mesh| {
+                let positions = mesh.indices.iter()
+                    .map(|&i| mesh.vertices[i as usize].position)
+                    .collect::<Vec<_>>();
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Debug Wire Buffer", mesh.name)),
+                    contents: bytemuck::cast_slice(&positions),
+                    usage: wgpu::BufferUsages::VERTEX
+                });
+                (buffer, positions.len() as u32)
+            }).collect();
+        }
+        if self.show_vectors && self.vector_buffers.len() != model.meshes.len() {
+            self.vector_buffers = model.meshes.iter().map(|mesh| {
+                let lines = mesh.vertices.iter().flat_map(|v| {
+                    let [x, y, z, w] = v.tangent;
+                    let bitangent = Vector3::from(v.normal).cross(Vector3::new(x, y, z)) * w;
+                    [v.normal, [x, y, z], bitangent.into()].into_iter().enumerate().flat_map(move |(kind, direction)| {
+                        [0.0, 1.0].map(|along| DebugLineVertex {
+                            position: v.position,
+                            direction,
+                            along,
+                            kind: kind as u32
+                        })
+                    })
+                }).collect::<Vec<_>>();
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Debug Vector Buffer", mesh.name)),
+                    contents: bytemuck::cast_slice(&lines),
+                    usage: wgpu::BufferUsages::VERTEX
+                });
+                (buffer, lines.len() as u32)
+            }).collect();
+        }
+    }
+
+    // 实例缓冲需要已经设置在槽 1。调试视图代替着色时先画它，
+    // 再叠加线框和向量
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup
+    ) {
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        // 调试视图和线框的管线只画三角形，点云跳过
+        let triangles = || model.meshes.iter().filter(|m| m.topology == wgpu::PrimitiveTopology::TriangleList);
+        if let (true, Some((_, pipeline))) = (self.replaces_shading(), &self.view_pipeline) {
+            render_pass.set_pipeline(pipeline);
+            for mesh in triangles() {
+                render_pass.set_vertex_buffer(0, mesh.position_buffer.slice(..));
+                if let Some(attributes) = &mesh.attribute_buffer {
+                    render_pass.set_vertex_buffer(2, attributes.slice(..));
+                }
+                mesh.draw_elements(render_pass, instances.clone());
+            }
+        }
+        if let (DebugMode::Wireframe, Some(pipeline)) = (self.mode, &self.wire_pipeline) {
+            render_pass.set_pipeline(pipeline);
+            if self.line_mode {
+                for mesh in triangles() {
+                    render_pass.set_vertex_buffer(0, mesh.position_buffer.slice(..));
+                    mesh.draw_elements(render_pass, instances.clone());
+                }
+            } else {
+                for (buffer, count) in &self.wire_buffers {
+                    render_pass.set_vertex_buffer(0, buffer.slice(..));
+                    render_pass.draw(0..*count, instances.clone());
+                }
+            }
+        }
+        if let (true, Some(pipeline)) = (self.show_vectors, &self.vector_pipeline) {
+            render_pass.set_pipeline(pipeline);
+            for (buffer, count) in &self.vector_buffers {
+                render_pass.set_vertex_buffer(0, buffer.slice(..));
+                render_pass.draw(0..*count, instances.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugLineVertex, DebugUniform};
+    use crate::{instance::InstanceRaw, shaders::reflect::{gpu_fields, ShaderReflection}, vertex::{PositionVertex, Vertex, VertexLayout}};
+
+    #[test]
+    fn layouts_match_wgsl() {
+        let shader = ShaderReflection::parse("debug/debug.wgsl", include_str!("debug.wgsl"));
+        shader.check_uniform::<DebugUniform>("debug", &gpu_fields!(DebugUniform: mode, line_length, znear, zfar));
+        shader.check_vertex_input("vs_view", &[PositionVertex::desc(), InstanceRaw::desc(), VertexLayout::Full.desc()]);
+        shader.check_vertex_input("vs_view_packed", &[PositionVertex::desc(), InstanceRaw::desc(), VertexLayout::Packed.desc()]);
+        shader.check_vertex_input("vs_wire", &[PositionVertex::desc(), InstanceRaw::desc()]);
+        shader.check_vertex_input("vs_wire_barycentric", &[PositionVertex::desc(), InstanceRaw::desc()]);
+        shader.check_vertex_input("vs_vector", &[DebugLineVertex::desc(), InstanceRaw::desc()]);
+    }
+}
@@ -0,0 +1,56 @@
+// 加载资源和初始化渲染器的错误。资源相关的都带上文件名，
+// 嵌入程序的应用可以按种类处理，比如文件找不到时换一个模型
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{file}: not found in resource roots [{roots}]")]
+    NotFound { file: String, roots: String },
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("{file}: failed to read from {root}")]
+    Io { file: String, root: String, #[source] source: std::io::Error },
+    #[cfg(target_arch = "wasm32")]
+    #[error("{file}: failed to fetch from {root}")]
+    Http { file: String, root: String, #[source] source: reqwest::Error },
+    #[error("{file}: not valid UTF-8")]
+    InvalidText { file: String, #[source] source: std::string::FromUtf8Error },
+    #[error("{file}: unsupported format ({reason})")]
+    UnsupportedFormat { file: String, reason: String },
+    #[error("{file}: failed to decode image")]
+    DecodeImage { file: String, #[source] source: image::ImageError },
+    #[error("{file}: malformed OBJ/MTL")]
+    Obj { file: String, #[source] source: tobj::LoadError },
+    #[error("{file}: malformed {format} ({reason})")]
+    Parse { file: String, format: String, reason: String },
+    #[error("{file}: mesh {mesh} refers to vertex {index} but has {vertex_count} vertices")]
+    InvalidIndex { file: String, mesh: String, index: u32, vertex_count: usize },
+    #[error("{file}: invalid resource pack ({reason})")]
+    InvalidPack { file: String, reason: String },
+    #[error("{file}: {what} is {value}, the GPU allows at most {limit}")]
+    GpuLimit { file: String, what: String, value: u64, limit: u64 },
+    #[error("{file}: {message}")]
+    Shader { file: String, message: String },
+    #[error("failed to create the window")]
+    Window(#[from] winit::error::OsError),
+    #[error("failed to create the window surface")]
+    CreateSurface(#[from] wgpu::CreateSurfaceError),
+    #[error("no compatible graphics adapter")]
+    NoAdapter,
+    #[error("failed to create the graphics device")]
+    RequestDevice(#[from] wgpu::RequestDeviceError),
+    #[error("failed to render a frame")]
+    Surface(#[from] wgpu::SurfaceError)
+}
+
+impl Error {
+    // 带上所有底层原因，用于日志
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(e) = source {
+            report += &format!(": {}", e);
+            source = e.source();
+        }
+        report
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
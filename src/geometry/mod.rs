@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::model::ModelVertex;
+
+mod normals;
+pub use normals::generate_normals;
+mod optimize;
+pub use optimize::{optimize, weld};
+mod tangents;
+pub use tangents::generate_tangents;
+mod uv;
+pub use uv::generate_uvs;
+
+// 按三角形的角修改顶点。顶点第一次被用到时原地修改，之后得到不同的结果时拆出新顶点，
+// 没有被用到的顶点保持不变
+fn split_corners(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    mut corner: impl FnMut(usize, &ModelVertex) -> ModelVertex
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut out_vertices = vertices.to_vec();
+    let mut out_indices = Vec::with_capacity(indices.len());
+    let mut assigned = vec![false; vertices.len()];
+    let mut splits: HashMap<(u32, Vec<u8>), u32> = HashMap::new();
+    for (i, &index) in indices.iter().enumerate() {
+        let vertex = corner(i, &vertices[index as usize]);
+        let slot = index as usize;
+        let index = if !assigned[slot] {
+            assigned[slot] = true;
+            out_vertices[slot] = vertex;
+            index
+        } else if bytemuck::bytes_of(&out_vertices[slot]) == bytemuck::bytes_of(&vertex) {
+            index
+        } else {
+            *splits.entry((index, bytemuck::bytes_of(&vertex).to_vec())).or_insert_with(|| {
+                out_vertices.push(vertex);
+                (out_vertices.len() - 1) as u32
+            })
+        };
+        out_indices.push(index);
+    }
+    (out_vertices, out_indices)
+}
+
+// 未归一化的面法线，长度是三角形面积的两倍
+fn face_normal(vertices: &[ModelVertex], triangle: &[u32]) -> Vector3<f32> {
+    let [p0, p1, p2] = [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+    (p1 - p0).cross(p2 - p0)
+}
+
+// 零向量和 NaN 返回 None
+fn try_normalize(v: Vector3<f32>) -> Option<Vector3<f32>> {
+    let length2 = v.magnitude2();
+    (length2 > 1e-20 && length2.is_finite()).then(|| v / length2.sqrt())
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Rad, Vector3};
+
+use crate::model::ModelVertex;
+use super::{face_normal, split_corners, try_normalize};
+
+// 为没有法线的网格生成法线。同一位置上，面法线夹角不超过 crease_angle 的三角形按角度加权平均，
+// 超过的保留硬边；crease_angle 为 0 时得到平直法线。
+// 硬边两侧的顶点会被拆开，所以返回新的顶点和索引。
+pub fn generate_normals(vertices: &[ModelVertex], indices: &[u32], crease_angle: Rad<f32>) -> (Vec<ModelVertex>, Vec<u32>) {
+    let face_normals = indices.chunks_exact(3)
+        .map(|triangle| try_normalize(face_normal(vertices, triangle)))
+        .collect::<Vec<_>>();
+    let corner_angles = indices.chunks_exact(3)
+        .flat_map(|triangle| [0, 1, 2].map(|i| corner_angle(vertices, triangle, i)))
+        .collect::<Vec<_>>();
+    // 按位置分组，UV 接缝两侧的顶点也能平滑
+    let mut corners_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (corner, &index) in indices.iter().enumerate() {
+        corners_at.entry(position_key(&vertices[index as usize])).or_default().push(corner);
+    }
+
+    let min_cos = crease_angle.0.cos() - 1e-6;
+    split_corners(vertices, indices, |corner, v| {
+        let face = corner / 3;
+        let normal = corners_at[&position_key(v)].iter()
+            .filter_map(|&other| {
+                let other_normal = face_normals[other / 3]?;
+                let smooth = match face_normals[face] {
+                    Some(normal) => other / 3 == face || normal.dot(other_normal) >= min_cos,
+                    // 退化的三角形没有自己的方向，跟随周围的面
+                    None => true
+                };
+                smooth.then(|| other_normal * corner_angles[other])
+            })
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, n| sum + n);
+        let normal = try_normalize(normal)
+            .or(face_normals[face])
+            .unwrap_or(Vector3::unit_y());
+        ModelVertex { normal: normal.into(), ..*v }
+    })
+}
+
+// -0.0 和 0.0 视为同一位置
+fn position_key(v: &ModelVertex) -> [u32; 3] {
+    v.position.map(|c| (c + 0.0).to_bits())
+}
+
+// 三角形第 i 个角的内角
+fn corner_angle(vertices: &[ModelVertex], triangle: &[u32], i: usize) -> f32 {
+    let p = |k: usize| Vector3::from(vertices[triangle[(i + k) % 3] as usize].position);
+    match (try_normalize(p(1) - p(0)), try_normalize(p(2) - p(0))) {
+        (Some(a), Some(b)) => a.dot(b).clamp(-1.0, 1.0).acos(),
+        _ => 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, InnerSpace, Vector3};
+
+    use super::generate_normals;
+    use crate::model::ModelVertex;
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex { position, normal: [0.0; 3], ..Default::default() }
+    }
+
+    // 沿 x 轴折成直角的两个三角形，一个朝 +Z，一个朝 +Y
+    fn fold() -> (Vec<ModelVertex>, Vec<u32>) {
+        let vertices = vec![
+            vertex([0.0, 0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0]),
+            vertex([0.5, 1.0, 0.0]),
+            vertex([0.5, 0.0, 1.0])
+        ];
+        (vertices, vec![0, 1, 2, 1, 0, 3])
+    }
+
+    fn normal(v: &ModelVertex) -> Vector3<f32> {
+        Vector3::from(v.normal)
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn flat_normals_split_shared_vertices() {
+        let (vertices, indices) = fold();
+        let (vertices, indices) = generate_normals(&vertices, &indices, Deg(0.0).into());
+        assert_eq!(vertices.len(), 6);
+        for (corner, &index) in indices.iter().enumerate() {
+            let expected = if corner < 3 { Vector3::unit_z() } else { Vector3::unit_y() };
+            assert_close(normal(&vertices[index as usize]), expected);
+        }
+    }
+
+    #[test]
+    fn crease_angle_keeps_hard_edges() {
+        let (vertices, indices) = fold();
+        let (vertices, _) = generate_normals(&vertices, &indices, Deg(60.0).into());
+        assert_eq!(vertices.len(), 6);
+    }
+
+    #[test]
+    fn smooth_normals_average_across_the_edge() {
+        let (vertices, indices) = fold();
+        let (vertices, out_indices) = generate_normals(&vertices, &indices, Deg(100.0).into());
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(out_indices, indices);
+        let diagonal = Vector3::new(0.0, 1.0, 1.0).normalize();
+        assert_close(normal(&vertices[0]), diagonal);
+        assert_close(normal(&vertices[1]), diagonal);
+        assert_close(normal(&vertices[2]), Vector3::unit_z());
+        assert_close(normal(&vertices[3]), Vector3::unit_y());
+    }
+
+    #[test]
+    fn smooths_across_uv_seams() {
+        // 第二个三角形用自己的顶点，位置相同但 UV 不同
+        let (mut vertices, _) = fold();
+        vertices.push(ModelVertex { tex_coords: [1.0, 1.0], ..vertex([0.0, 0.0, 0.0]) });
+        vertices.push(ModelVertex { tex_coords: [1.0, 1.0], ..vertex([1.0, 0.0, 0.0]) });
+        let (vertices, _) = generate_normals(&vertices, &[0, 1, 2, 5, 4, 3], Deg(100.0).into());
+        let diagonal = Vector3::new(0.0, 1.0, 1.0).normalize();
+        for i in [0, 1, 4, 5] {
+            assert_close(normal(&vertices[i]), diagonal);
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles_give_unit_normals() {
+        let (mut vertices, mut indices) = fold();
+        // 面积为 0 的三角形，和只有这种三角形的孤立顶点
+        vertices.push(vertex([2.0, 0.0, 0.0]));
+        vertices.push(vertex([5.0, 5.0, 5.0]));
+        indices.extend([0, 1, 4, 5, 5, 5]);
+        let (vertices, _) = generate_normals(&vertices, &indices, Deg(30.0).into());
+        for v in &vertices {
+            assert!(v.normal.iter().all(|c| c.is_finite()), "{:?}", v.normal);
+            assert!((normal(v).magnitude() - 1.0).abs() < 1e-5, "{:?}", v.normal);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::model::ModelVertex;
+use super::face_normal;
+
+// 模拟的 post-transform 顶点缓存大小
+const CACHE_SIZE: u32 = 16;
+
+// 合并完全相同的顶点，删除没有用到的顶点
+pub fn weld(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut out_vertices = Vec::new();
+    let mut remap: HashMap<&[u8], u32> = HashMap::new();
+    let out_indices = indices.iter().map(|&index| {
+        let vertex = &vertices[index as usize];
+        *remap.entry(bytemuck::bytes_of(vertex)).or_insert_with(|| {
+            out_vertices.push(*vertex);
+            (out_vertices.len() - 1) as u32
+        })
+    }).collect();
+    (out_vertices, out_indices)
+}
+
+// 与 meshoptimizer 的流程相同：先按顶点缓存重排三角形 (Tipsify)，再把结果分成若干簇，
+// 朝外的簇先画以减少 overdraw，最后按第一次使用的顺序重排顶点，方便顶点读取
+pub fn optimize(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let (indices, clusters) = tipsify(indices, vertices.len());
+    let indices = sort_clusters(vertices, &indices, &clusters);
+    reorder_vertices(vertices, &indices)
+}
+
+// Sander et al., "Fast Triangle Reordering for Vertex Locality and Reduced Overdraw"。
+// 返回重排后的索引和每个簇开始的三角形，簇在 Tipsify 跳到不相邻的顶点时结束
+fn tipsify(indices: &[u32], vertex_count: usize) -> (Vec<u32>, Vec<usize>) {
+    let triangle_count = indices.len() / 3;
+    // 每个顶点还没有输出的三角形数
+    let mut live = vec![0u32; vertex_count];
+    for &index in &indices[..triangle_count * 3] {
+        live[index as usize] += 1;
+    }
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for v in 0..vertex_count {
+        offsets[v + 1] = offsets[v] + live[v] as usize;
+    }
+    let mut filled = offsets.clone();
+    let mut adjacency = vec![0usize; triangle_count * 3];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners {
+            adjacency[filled[index as usize]] = triangle;
+            filled[index as usize] += 1;
+        }
+    }
+
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    let mut clusters = Vec::new();
+    let mut emitted = vec![false; triangle_count];
+    let mut cache_time = vec![0u32; vertex_count];
+    let mut time = CACHE_SIZE + 1;
+    let mut dead_end: Vec<u32> = Vec::new();
+    let mut cursor = 0;
+    let mut fanning = indices.chunks_exact(3).next().map(|triangle| triangle[0]);
+    let mut adjacent = true;
+    while let Some(vertex) = fanning {
+        if !adjacent {
+            clusters.push(output.len() / 3);
+        }
+        let mut candidates = Vec::new();
+        for &triangle in &adjacency[offsets[vertex as usize]..offsets[vertex as usize + 1]] {
+            if emitted[triangle] {
+                continue;
+            }
+            emitted[triangle] = true;
+            for &index in &indices[triangle * 3..triangle * 3 + 3] {
+                output.push(index);
+                dead_end.push(index);
+                candidates.push(index);
+                live[index as usize] -= 1;
+                if time - cache_time[index as usize] > CACHE_SIZE {
+                    cache_time[index as usize] = time;
+                    time += 1;
+                }
+            }
+        }
+
+        // 优先选还在缓存里、且剩下的三角形输出后不会被挤出缓存的顶点
+        let mut best = None;
+        let mut best_priority = -1i64;
+        for &candidate in &candidates {
+            let v = candidate as usize;
+            if live[v] == 0 {
+                continue;
+            }
+            let age = time - cache_time[v];
+            let priority = if age + 2 * live[v] <= CACHE_SIZE { age as i64 } else { 0 };
+            if priority > best_priority {
+                best = Some(candidate);
+                best_priority = priority;
+            }
+        }
+        adjacent = best.is_some();
+        fanning = best.or_else(|| {
+            while let Some(index) = dead_end.pop() {
+                if live[index as usize] > 0 {
+                    return Some(index);
+                }
+            }
+            while cursor < vertex_count {
+                if live[cursor] > 0 {
+                    return Some(cursor as u32);
+                }
+                cursor += 1;
+            }
+            None
+        });
+    }
+    if !output.is_empty() && clusters.first() != Some(&0) {
+        clusters.insert(0, 0);
+    }
+    (output, clusters)
+}
+
+// 按簇的朝向排序：簇的中心相对网格中心越是沿着簇的法线向外，越可能挡住别的簇，先画
+fn sort_clusters(vertices: &[ModelVertex], indices: &[u32], clusters: &[usize]) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let centroid = |triangle: &[u32]| {
+        triangle.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, &i| sum + Vector3::from(vertices[i as usize].position)) / 3.0
+    };
+    // 面积加权
+    let weighted = |range: std::ops::Range<usize>| {
+        indices[range.start * 3..range.end * 3].chunks_exact(3).fold(
+            (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0), 0.0),
+            |(center, normal, area), triangle| {
+                let n = face_normal(vertices, triangle);
+                let a = n.magnitude();
+                (center + centroid(triangle) * a, normal + n, area + a)
+            }
+        )
+    };
+    let (center, _, area) = weighted(0..triangle_count);
+    let mesh_center = if area > 0.0 { center / area } else { center };
+
+    let mut sorted = clusters.iter().enumerate().map(|(i, &start)| {
+        let end = clusters.get(i + 1).copied().unwrap_or(triangle_count);
+        let (center, normal, area) = weighted(start..end);
+        let facing = if area > 0.0 && normal.magnitude2() > 0.0 {
+            (center / area - mesh_center).dot(normal.normalize())
+        } else {
+            0.0
+        };
+        (facing, start..end)
+    }).collect::<Vec<_>>();
+    // 稳定排序，保证结果确定
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    sorted.into_iter().flat_map(|(_, range)| indices[range.start * 3..range.end * 3].iter().copied()).collect()
+}
+
+// 按索引中第一次出现的顺序排列顶点
+fn reorder_vertices(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut out_vertices = Vec::with_capacity(vertices.len());
+    let out_indices = indices.iter().map(|&index| {
+        let slot = &mut remap[index as usize];
+        if *slot == u32::MAX {
+            *slot = out_vertices.len() as u32;
+            out_vertices.push(vertices[index as usize]);
+        }
+        *slot
+    }).collect();
+    (out_vertices, out_indices)
+}
+
+// 模拟 FIFO 顶点缓存，每个三角形平均需要变换的顶点数 (ACMR)
+#[cfg(test)]
+fn acmr(indices: &[u32]) -> f32 {
+    let mut cache = std::collections::VecDeque::new();
+    let mut misses = 0;
+    for &index in indices {
+        if !cache.contains(&index) {
+            misses += 1;
+            cache.push_back(index);
+            if cache.len() > CACHE_SIZE as usize {
+                cache.pop_front();
+            }
+        }
+    }
+    misses as f32 / (indices.len() / 3) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::{acmr, optimize, weld};
+    use crate::model::ModelVertex;
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex { position, ..Default::default() }
+    }
+
+    // n x n 个格子的网格，三角形按随机顺序排列，顶点不共享
+    fn scrambled_grid(n: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let mut triangles = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let p = |dx: u32, dy: u32| [(x + dx) as f32, (y + dy) as f32, 0.0];
+                triangles.push([p(0, 0), p(1, 0), p(1, 1)]);
+                triangles.push([p(0, 0), p(1, 1), p(0, 1)]);
+            }
+        }
+        // 固定种子的线性同余，结果可重复
+        let mut seed = 12345u32;
+        for i in (1..triangles.len()).rev() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            triangles.swap(i, (seed >> 16) as usize % (i + 1));
+        }
+        let vertices = triangles.iter().flatten().map(|&p| vertex(p)).collect::<Vec<_>>();
+        let indices = (0..vertices.len() as u32).collect();
+        (vertices, indices)
+    }
+
+    // 每个三角形的三个顶点位置，与顶点编号无关
+    fn triangle_set(vertices: &[ModelVertex], indices: &[u32]) -> BTreeSet<[[u32; 3]; 3]> {
+        indices.chunks(3).map(|t| {
+            let mut corners = [0, 1, 2].map(|i| vertices[t[i] as usize].position.map(f32::to_bits));
+            // 保持环绕方向，只旋转到最小的顶点开头
+            let first = (0..3).min_by_key(|&i| corners[i]).unwrap();
+            corners.rotate_left(first);
+            corners
+        }).collect()
+    }
+
+    #[test]
+    fn weld_merges_identical_vertices() {
+        let (vertices, indices) = scrambled_grid(4);
+        let (welded, welded_indices) = weld(&vertices, &indices);
+        assert_eq!(welded.len(), 25);
+        assert_eq!(triangle_set(&vertices, &indices), triangle_set(&welded, &welded_indices));
+    }
+
+    #[test]
+    fn weld_keeps_vertices_that_differ() {
+        let a = vertex([0.0, 0.0, 0.0]);
+        let b = ModelVertex { tex_coords: [1.0, 0.0], ..a };
+        let (welded, indices) = weld(&[a, b, a, vertex([1.0, 0.0, 0.0])], &[0, 3, 1, 2, 3, 1]);
+        assert_eq!(welded.len(), 3);
+        assert_eq!(indices, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn optimize_improves_vertex_cache_use() {
+        let (vertices, indices) = scrambled_grid(32);
+        let (vertices, indices) = weld(&vertices, &indices);
+        let before = acmr(&indices);
+        let (optimized, optimized_indices) = optimize(&vertices, &indices);
+        let after = acmr(&optimized_indices);
+        assert!(after < before * 0.7, "ACMR {} -> {}", before, after);
+        // 三角形和环绕方向不变
+        assert_eq!(triangle_set(&vertices, &indices), triangle_set(&optimized, &optimized_indices));
+        assert_eq!(optimized.len(), vertices.len());
+    }
+
+    #[test]
+    fn optimize_orders_vertices_by_first_use() {
+        let (vertices, indices) = scrambled_grid(8);
+        let (vertices, indices) = weld(&vertices, &indices);
+        let (_, indices) = optimize(&vertices, &indices);
+        let mut next = 0;
+        for &index in &indices {
+            assert!(index <= next);
+            if index == next {
+                next += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn optimize_is_deterministic() {
+        let (vertices, indices) = scrambled_grid(8);
+        let (vertices, indices) = weld(&vertices, &indices);
+        let first = optimize(&vertices, &indices).1;
+        assert_eq!(optimize(&vertices, &indices).1, first);
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let (vertices, indices) = optimize(&[], &[]);
+        assert!(vertices.is_empty() && indices.is_empty());
+    }
+}
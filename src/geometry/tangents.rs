@@ -0,0 +1,219 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::model::ModelVertex;
+use super::{split_corners, try_normalize};
+
+// 按 MikkTSpace 生成切线，与 Blender 等工具烘焙法线贴图时的切线空间一致。
+// tangent.w 是副切线的方向：bitangent = w * cross(normal, tangent)。
+// 同一个顶点在不同三角形里得到不同的切线时（比如 UV 镜像的接缝）会被拆成多个顶点，
+// 所以返回新的顶点和索引。
+pub fn generate_tangents(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut corners = Corners {
+        vertices,
+        indices,
+        tangents: vec![None; indices.len()]
+    };
+    if !bevy_mikktspace::generate_tangents(&mut corners) {
+        log::warn!("MikkTSpace failed, using fallback tangents");
+    }
+    // 没有被三角形用到的顶点也要有合法的切线
+    let fallback = vertices.iter()
+        .map(|v| ModelVertex { tangent: orthonormalize(v.normal, None), ..*v })
+        .collect::<Vec<_>>();
+    split_corners(&fallback, indices, |corner, v| ModelVertex {
+        tangent: orthonormalize(v.normal, corners.tangents[corner]),
+        ..*v
+    })
+}
+
+// 三角形的每个角，MikkTSpace 按 (face, vert) 读写
+struct Corners<'a> {
+    vertices: &'a [ModelVertex],
+    indices: &'a [u32],
+    tangents: Vec<Option<[f32; 4]>>
+}
+
+impl Corners<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &ModelVertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl bevy_mikktspace::Geometry for Corners<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert).tex_coords
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = Some(tangent);
+    }
+}
+
+// 让切线与法线正交并归一化。UV 退化时 MikkTSpace 可能给出零向量或与法线平行的切线，
+// 这时任选一个与法线垂直的方向，保证结果不会是 inf/NaN
+fn orthonormalize(normal: [f32; 3], tangent: Option<[f32; 4]>) -> [f32; 4] {
+    let normal = try_normalize(normal.into()).unwrap_or(Vector3::unit_z());
+    let [x, y, z, w] = tangent.unwrap_or([0.0; 4]);
+    let sign = if w < 0.0 { -1.0 } else { 1.0 };
+    let project = |t: Vector3<f32>| t - normal * normal.dot(t);
+
+    let tangent = project(Vector3::new(x, y, z));
+    let tangent = if tangent.magnitude2() > 1e-12 && tangent.magnitude2().is_finite() {
+        tangent
+    } else {
+        // 选与法线夹角最大的坐标轴
+        let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+        project(axis)
+    };
+    let tangent = tangent.normalize();
+    [tangent.x, tangent.y, tangent.z, sign]
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Vector3};
+
+    use super::generate_tangents;
+    use crate::model::ModelVertex;
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+        ModelVertex { position, tex_coords, normal: [0.0, 0.0, 1.0], ..Default::default() }
+    }
+
+    // z = 0 平面上的单位正方形，法线 +Z
+    fn quad(uvs: [[f32; 2]; 4]) -> (Vec<ModelVertex>, Vec<u32>) {
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+        let vertices = positions.iter().zip(uvs).map(|(&p, uv)| vertex(p, uv)).collect();
+        (vertices, vec![0, 1, 2, 0, 2, 3])
+    }
+
+    fn tangent(v: &ModelVertex) -> Vector3<f32> {
+        Vector3::new(v.tangent[0], v.tangent[1], v.tangent[2])
+    }
+
+    fn bitangent(v: &ModelVertex) -> Vector3<f32> {
+        Vector3::from(v.normal).cross(tangent(v)) * v.tangent[3]
+    }
+
+    fn assert_orthonormal(vertices: &[ModelVertex]) {
+        for v in vertices {
+            let t = tangent(v);
+            assert!(v.tangent.iter().all(|c| c.is_finite()), "{:?}", v.tangent);
+            assert!((t.magnitude() - 1.0).abs() < 1e-5, "{:?}", v.tangent);
+            assert!(t.dot(Vector3::from(v.normal)).abs() < 1e-5, "{:?}", v.tangent);
+            assert!(v.tangent[3] == 1.0 || v.tangent[3] == -1.0, "{:?}", v.tangent);
+        }
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn follows_uv_directions() {
+        let (vertices, indices) = quad([[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        let (vertices, out_indices) = generate_tangents(&vertices, &indices);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(out_indices, indices);
+        assert_orthonormal(&vertices);
+        for v in &vertices {
+            assert_close(tangent(v), Vector3::unit_x());
+            assert_close(bitangent(v), Vector3::unit_y());
+            assert_eq!(v.tangent[3], 1.0);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_handedness() {
+        // u 随 x 减小，v 随 y 增大
+        let (vertices, indices) = quad([[1.0, 0.0], [0.0, 0.0], [0.0, 1.0], [1.0, 1.0]]);
+        let (vertices, _) = generate_tangents(&vertices, &indices);
+        assert_orthonormal(&vertices);
+        for v in &vertices {
+            assert_close(tangent(v), -Vector3::unit_x());
+            assert_close(bitangent(v), Vector3::unit_y());
+            assert_eq!(v.tangent[3], -1.0);
+        }
+    }
+
+    #[test]
+    fn mirror_seam_splits_shared_vertices() {
+        // 两个正方形共用 x = 1 的边，右边的 UV 镜像
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([1.0, 1.0, 0.0], [1.0, 1.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 1.0]),
+            vertex([2.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([2.0, 1.0, 0.0], [0.0, 1.0])
+        ];
+        let indices = vec![0, 1, 2, 0, 2, 3, 1, 4, 5, 1, 5, 2];
+        let (out_vertices, out_indices) = generate_tangents(&vertices, &indices);
+        assert_orthonormal(&out_vertices);
+        assert_eq!(out_vertices.len(), 8);
+        for (corner, &index) in out_indices.iter().enumerate() {
+            let v = &out_vertices[index as usize];
+            let expected = if corner < 6 { 1.0 } else { -1.0 };
+            assert_eq!(v.tangent[3], expected, "corner {}", corner);
+            assert_close(bitangent(v), Vector3::unit_y());
+            // 拆出来的顶点除了切线以外不变
+            vertices[indices[corner] as usize].tangent = v.tangent;
+            assert_eq!(bytemuck::bytes_of(v), bytemuck::bytes_of(&vertices[indices[corner] as usize]));
+        }
+    }
+
+    #[test]
+    fn degenerate_uvs_give_finite_tangents() {
+        let (vertices, indices) = quad([[0.5, 0.5]; 4]);
+        let (vertices, _) = generate_tangents(&vertices, &indices);
+        assert_orthonormal(&vertices);
+    }
+
+    #[test]
+    fn degenerate_triangles_give_finite_tangents() {
+        let mut vertices = vec![
+            // 面积为 0
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([2.0, 0.0, 0.0], [2.0, 0.0]),
+            // UV 共线
+            vertex([0.0, 0.0, 1.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 1.0], [1.0, 1.0]),
+            vertex([0.0, 1.0, 1.0], [2.0, 2.0]),
+            // 重复的索引
+            vertex([3.0, 3.0, 3.0], [0.0, 0.0])
+        ];
+        // 法线为 0 的顶点
+        vertices.push(ModelVertex { normal: [0.0; 3], ..vertex([4.0, 4.0, 4.0], [1.0, 1.0]) });
+        let indices = vec![0, 1, 2, 3, 4, 5, 6, 6, 6, 6, 7, 6];
+        let (vertices, _) = generate_tangents(&vertices, &indices);
+        for v in &vertices {
+            assert!(v.tangent.iter().all(|c| c.is_finite()), "{:?}", v.tangent);
+        }
+        assert_orthonormal(&vertices[..7]);
+    }
+
+    #[test]
+    fn unused_vertices_get_a_tangent() {
+        let mut vertices = quad([[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]).0;
+        vertices.push(ModelVertex { normal: [1.0, 0.0, 0.0], ..vertex([5.0, 5.0, 5.0], [0.0, 0.0]) });
+        let (vertices, _) = generate_tangents(&vertices, &[0, 1, 2]);
+        assert_orthonormal(&vertices);
+    }
+}
@@ -0,0 +1,145 @@
+use cgmath::Vector3;
+
+use crate::model::ModelVertex;
+use super::{face_normal, split_corners};
+
+// 没有纹理坐标时采用的投影
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UvProjection {
+    // 网格没有面积（只有点或线），全部为 0
+    Zero,
+    // 网格是平的，投影到包围盒最大的两个轴上
+    Planar,
+    // 每个三角形按面法线的主方向投影到包围盒对应的面上
+    Box
+}
+
+// 按网格的形状选择投影方式生成纹理坐标，纹理坐标按包围盒最长边缩放到 0..1。
+// Box 投影时不同朝向的面之间会拆开顶点，所以返回新的顶点和索引。
+pub fn generate_uvs(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>, UvProjection) {
+    let Some((min, max)) = bounds(vertices) else {
+        return (vertices.to_vec(), indices.to_vec(), UvProjection::Zero);
+    };
+    let extent = max - min;
+    let mut axes = [0, 1, 2];
+    axes.sort_by(|&a, &b| extent[b].total_cmp(&extent[a]));
+    let size = extent[axes[0]];
+    let relative = |v: &ModelVertex| (Vector3::from(v.position) - min) / size;
+
+    if !size.is_normal() || extent[axes[1]] <= size * 1e-6 {
+        let vertices = vertices.iter().map(|v| ModelVertex { tex_coords: [0.0; 2], ..*v }).collect();
+        return (vertices, indices.to_vec(), UvProjection::Zero);
+    }
+    if extent[axes[2]] <= size * 1e-3 {
+        // 保持坐标轴原来的顺序，XZ 平面上 u 沿 x，v 沿 z
+        let (u, v) = (axes[0].min(axes[1]), axes[0].max(axes[1]));
+        let vertices = vertices.iter()
+            .map(|vertex| {
+                let p = relative(vertex);
+                ModelVertex { tex_coords: [p[u], p[v]], ..*vertex }
+            })
+            .collect();
+        return (vertices, indices.to_vec(), UvProjection::Planar);
+    }
+
+    let extent = extent / size;
+    let faces = indices.chunks_exact(3)
+        .map(|triangle| face_normal(vertices, triangle))
+        .collect::<Vec<_>>();
+    let (vertices, indices) = split_corners(vertices, indices, |corner, vertex| {
+        let n = faces[corner / 3];
+        let p = relative(vertex);
+        // 从外面看过去纹理不是镜像的
+        let tex_coords = if n.x.abs() >= n.y.abs() && n.x.abs() >= n.z.abs() {
+            if n.x >= 0.0 { [extent.z - p.z, p.y] } else { [p.z, p.y] }
+        } else if n.y.abs() >= n.z.abs() {
+            if n.y >= 0.0 { [p.x, extent.z - p.z] } else { [p.x, p.z] }
+        } else if n.z >= 0.0 {
+            [p.x, p.y]
+        } else {
+            [extent.x - p.x, p.y]
+        };
+        ModelVertex { tex_coords, ..*vertex }
+    });
+    (vertices, indices, UvProjection::Box)
+}
+
+fn bounds(vertices: &[ModelVertex]) -> Option<(Vector3<f32>, Vector3<f32>)> {
+    let mut positions = vertices.iter().map(|v| Vector3::from(v.position));
+    let first = positions.next()?;
+    Some(positions.fold((first, first), |(min, max), p| (
+        Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+        Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z))
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_uvs, UvProjection};
+    use crate::model::ModelVertex;
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex { position, tex_coords: [0.5, 0.5], ..Default::default() }
+    }
+
+    // 三角形在纹理空间里的有向面积
+    fn uv_area(vertices: &[ModelVertex], triangle: &[u32]) -> f32 {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize].tex_coords);
+        ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])) * 0.5
+    }
+
+    #[test]
+    fn flat_mesh_uses_planar_projection() {
+        // XZ 平面上 2x1 的长方形
+        let vertices = [[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [2.0, 0.0, 1.0], [0.0, 0.0, 1.0]].map(vertex);
+        let indices = [0, 1, 2, 0, 2, 3];
+        let (vertices, out_indices, projection) = generate_uvs(&vertices, &indices);
+        assert_eq!(projection, UvProjection::Planar);
+        assert_eq!(out_indices, indices);
+        let uvs = vertices.iter().map(|v| v.tex_coords).collect::<Vec<_>>();
+        assert_eq!(uvs, [[0.0, 0.0], [1.0, 0.0], [1.0, 0.5], [0.0, 0.5]]);
+    }
+
+    #[test]
+    fn closed_mesh_uses_box_projection() {
+        // 单位立方体，8 个共享顶点，三角形朝外
+        let vertices = [
+            [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]
+        ].map(vertex);
+        let indices = [
+            4, 5, 6, 4, 6, 7, // +z
+            1, 0, 3, 1, 3, 2, // -z
+            5, 1, 2, 5, 2, 6, // +x
+            0, 4, 7, 0, 7, 3, // -x
+            7, 6, 2, 7, 2, 3, // +y
+            0, 1, 5, 0, 5, 4  // -y
+        ];
+        let (vertices, indices, projection) = generate_uvs(&vertices, &indices);
+        assert_eq!(projection, UvProjection::Box);
+        // 共享的顶点在不同朝向的面上得到不同的 UV，被拆开
+        assert!(vertices.len() > 8);
+        for triangle in indices.chunks(3) {
+            // 每个面占满 0..1，且从外面看不是镜像的
+            assert!((uv_area(&vertices, triangle) - 0.5).abs() < 1e-6, "{:?}", triangle);
+        }
+        for v in &vertices {
+            assert!(v.tex_coords.iter().all(|c| (0.0..=1.0).contains(c)), "{:?}", v.tex_coords);
+        }
+    }
+
+    #[test]
+    fn line_gets_zero_uvs() {
+        let vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]].map(vertex);
+        let (vertices, _, projection) = generate_uvs(&vertices, &[0, 1, 2]);
+        assert_eq!(projection, UvProjection::Zero);
+        assert!(vertices.iter().all(|v| v.tex_coords == [0.0, 0.0]));
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let (vertices, indices, projection) = generate_uvs(&[], &[]);
+        assert!(vertices.is_empty() && indices.is_empty());
+        assert_eq!(projection, UvProjection::Zero);
+    }
+}
@@ -0,0 +1,51 @@
+// OBJ 以外的网格格式。解析成和格式无关的三角网格，生成法线、切线等处理和 OBJ 一样，
+// 见 resources::load_model_data
+use crate::error::{Error, Result};
+
+mod ply;
+mod stl;
+
+// 文件里没有的属性是 None
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportedMesh {
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Option<Vec<[f32; 3]>>,
+    pub tex_coords: Option<Vec<[f32; 2]>>,
+    // 文件里的 RGBA 换算到 0 到 1，RGB 还是 sRGB
+    pub colors: Option<Vec<[f32; 4]>>,
+    // 三角形，多边形已经拆开。只有顶点的 PLY 是点云，这里为空
+    pub indices: Vec<u32>
+}
+
+pub fn parse_ply(file_name: &str, data: &[u8]) -> Result<ImportedMesh> {
+    let mesh = ply::parse(data).map_err(|e| parse_error(file_name, "PLY", e))?;
+    check_indices(file_name, mesh)
+}
+
+pub fn parse_stl(file_name: &str, data: &[u8]) -> Result<ImportedMesh> {
+    stl::parse(data).map_err(|e| parse_error(file_name, "STL", e))
+}
+
+fn parse_error(file_name: &str, format: &str, e: anyhow::Error) -> Error {
+    Error::Parse { file: file_name.to_owned(), format: format.to_owned(), reason: format!("{:#}", e) }
+}
+
+fn check_indices(file_name: &str, mesh: ImportedMesh) -> Result<ImportedMesh> {
+    match mesh.indices.iter().find(|&&i| i as usize >= mesh.positions.len()) {
+        Some(&index) => Err(Error::InvalidIndex {
+            file: file_name.to_owned(),
+            mesh: mesh.name,
+            index,
+            vertex_count: mesh.positions.len()
+        }),
+        None => Ok(mesh)
+    }
+}
+
+// 凸多边形按扇形拆成三角形
+fn triangulate(polygon: &[u32], indices: &mut Vec<u32>) {
+    for i in 2..polygon.len() {
+        indices.extend_from_slice(&[polygon[0], polygon[i - 1], polygon[i]]);
+    }
+}
@@ -0,0 +1,342 @@
+// PLY (Stanford Triangle Format)，支持 ascii、binary_little_endian 和 binary_big_endian。
+// 读取 vertex 的位置、法线、纹理坐标和颜色，face 的顶点索引，其他元素和属性跳过
+use anyhow::{bail, Context};
+
+use super::{triangulate, ImportedMesh};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    I8, U8, I16, U16, I32, U32, F32, F64
+}
+
+impl Type {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Type::I8,
+            "uchar" | "uint8" => Type::U8,
+            "short" | "int16" => Type::I16,
+            "ushort" | "uint16" => Type::U16,
+            "int" | "int32" => Type::I32,
+            "uint" | "uint32" => Type::U32,
+            "float" | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            _ => bail!("unknown property type {}", name)
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8
+        }
+    }
+
+    // 颜色分量换算到 0 到 1：整数按类型的最大值，浮点数认为已经是 0 到 1
+    fn unit_scale(self) -> f64 {
+        match self {
+            Type::I8 => i8::MAX as f64,
+            Type::U8 => u8::MAX as f64,
+            Type::I16 => i16::MAX as f64,
+            Type::U16 => u16::MAX as f64,
+            Type::I32 => i32::MAX as f64,
+            Type::U32 => u32::MAX as f64,
+            Type::F32 | Type::F64 => 1.0
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    // 列表属性是 Some(个数的类型)
+    list: Option<Type>,
+    ty: Type
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+pub fn parse(data: &[u8]) -> anyhow::Result<ImportedMesh> {
+    let (format, elements, body) = parse_header(data)?;
+    let mut values = match format {
+        Format::Ascii => Values::Ascii(std::str::from_utf8(body).context("ASCII body is not UTF-8")?.split_ascii_whitespace()),
+        Format::BinaryLittleEndian => Values::Binary(body, false),
+        Format::BinaryBigEndian => Values::Binary(body, true)
+    };
+
+    let mut mesh = ImportedMesh::default();
+    let mut polygon = Vec::new();
+    for element in &elements {
+        let find = |names: &[&str]| element.properties.iter().position(|p| p.list.is_none() && names.contains(&p.name.as_str()));
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+        let tex_coord = [find(&["u", "s", "texture_u", "texture_s"]), find(&["v", "t", "texture_v", "texture_t"])];
+        let color = [find(&["red", "r", "diffuse_red"]), find(&["green", "g", "diffuse_green"]), find(&["blue", "b", "diffuse_blue"])];
+        let alpha = find(&["alpha", "a", "diffuse_alpha"]);
+        let is_vertex = element.name == "vertex";
+        if is_vertex {
+            if position.contains(&None) {
+                bail!("vertex element has no x, y and z");
+            }
+            // 文件头里的个数不可信，不超过数据的字节数
+            let capacity = element.count.min(body.len());
+            mesh.positions.reserve(capacity);
+            if !normal.contains(&None) {
+                mesh.normals = Some(Vec::with_capacity(capacity));
+            }
+            if !tex_coord.contains(&None) {
+                mesh.tex_coords = Some(Vec::with_capacity(capacity));
+            }
+            if !color.contains(&None) {
+                mesh.colors = Some(Vec::with_capacity(capacity));
+            }
+        }
+        let indices = element.properties.iter()
+            .position(|p| p.list.is_some() && (p.name == "vertex_indices" || p.name == "vertex_index"));
+        let is_face = element.name == "face";
+        if is_face && indices.is_none() {
+            bail!("face element has no vertex_indices");
+        }
+
+        let mut row = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.list {
+                    None => row[i] = values.read(property.ty)?,
+                    Some(count_type) => {
+                        let count = values.read(count_type)? as usize;
+                        polygon.clear();
+                        for _ in 0..count {
+                            // 负数的索引会变成很大的数，之后作为越界的索引报错
+                            polygon.push(values.read(property.ty)? as i64 as u32);
+                        }
+                        if is_face && Some(i) == indices {
+                            triangulate(&polygon, &mut mesh.indices);
+                        }
+                    }
+                }
+            }
+            if is_vertex {
+                let get = |slots: &[Option<usize>]| slots.iter().map(|slot| row[slot.unwrap()] as f32).collect::<Vec<_>>();
+                mesh.positions.push(get(&position).try_into().unwrap());
+                if let Some(normals) = &mut mesh.normals {
+                    normals.push(get(&normal).try_into().unwrap());
+                }
+                if let Some(tex_coords) = &mut mesh.tex_coords {
+                    tex_coords.push(get(&tex_coord).try_into().unwrap());
+                }
+                if let Some(colors) = &mut mesh.colors {
+                    let channel = |slot: Option<usize>| slot.map(|i| {
+                        let scale = element.properties[i].ty.unit_scale();
+                        (row[i] / scale).clamp(0.0, 1.0) as f32
+                    });
+                    let [r, g, b] = color.map(|slot| channel(slot).unwrap());
+                    colors.push([r, g, b, channel(alpha).unwrap_or(1.0)]);
+                }
+            }
+        }
+    }
+    // 没有面的文件是点云，indices 为空
+    if mesh.positions.is_empty() {
+        bail!("no vertices");
+    }
+    Ok(mesh)
+}
+
+fn parse_header(data: &[u8]) -> anyhow::Result<(Format, Vec<Element>, &[u8])> {
+    const END: &[u8] = b"end_header";
+    let end = data.windows(END.len()).position(|w| w == END).context("no end_header")?;
+    // end_header 这一行的换行之后是数据
+    let body_start = data[end..].iter().position(|&b| b == b'\n').map_or(data.len(), |i| end + i + 1);
+    let header = std::str::from_utf8(&data[..end]).context("header is not UTF-8")?;
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        bail!("not a PLY file");
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            ["format", name, _version] => format = Some(match *name {
+                "ascii" => Format::Ascii,
+                "binary_little_endian" => Format::BinaryLittleEndian,
+                "binary_big_endian" => Format::BinaryBigEndian,
+                _ => bail!("unknown format {}", name)
+            }),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().with_context(|| format!("invalid element count {}", count))?,
+                properties: Vec::new()
+            }),
+            ["property", "list", count_type, ty, name] => elements.last_mut().context("property before element")?.properties.push(Property {
+                name: name.to_string(),
+                list: Some(Type::parse(count_type)?),
+                ty: Type::parse(ty)?
+            }),
+            ["property", ty, name] => elements.last_mut().context("property before element")?.properties.push(Property {
+                name: name.to_string(),
+                list: None,
+                ty: Type::parse(ty)?
+            }),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => bail!("invalid header line {:?}", line)
+        }
+    }
+    Ok((format.context("no format line")?, elements, &data[body_start..]))
+}
+
+enum Values<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    // 数据和是否大端
+    Binary(&'a [u8], bool)
+}
+
+impl Values<'_> {
+    fn read(&mut self, ty: Type) -> anyhow::Result<f64> {
+        match self {
+            Values::Ascii(words) => {
+                let word = words.next().context("unexpected end of file")?;
+                word.parse().with_context(|| format!("invalid number {}", word))
+            }
+            Values::Binary(data, big_endian) => {
+                if data.len() < ty.size() {
+                    bail!("unexpected end of file");
+                }
+                let (bytes, rest) = data.split_at(ty.size());
+                *data = rest;
+                let mut b = [0u8; 8];
+                b[..bytes.len()].copy_from_slice(bytes);
+                if *big_endian {
+                    b[..bytes.len()].reverse();
+                }
+                Ok(match ty {
+                    Type::I8 => b[0] as i8 as f64,
+                    Type::U8 => b[0] as f64,
+                    Type::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::F64 => f64::from_le_bytes(b)
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    const ASCII: &str = "ply
+format ascii 1.0
+comment a colored quad and a triangle
+element vertex 5
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 2
+property list uchar int vertex_indices
+property uchar flags
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+0 0 1 0 0 0
+4 0 1 2 3 7
+3 0 3 4 7
+";
+
+    #[test]
+    fn ascii_with_colors() {
+        let mesh = parse(ASCII.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.positions[2], [1.0, 1.0, 0.0]);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3, 0, 3, 4]);
+        let colors = mesh.colors.unwrap();
+        assert_eq!(colors[1], [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(colors[3], [1.0; 4]);
+        assert!(mesh.normals.is_none() && mesh.tex_coords.is_none());
+    }
+
+    // 同样的网格写成二进制
+    fn binary(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian { "binary_big_endian" } else { "binary_little_endian" };
+        let mut data = ASCII.replace("format ascii", &format!("format {}", format));
+        data.truncate(data.find("end_header").unwrap() + "end_header\n".len());
+        let mut data = data.into_bytes();
+        let float = |data: &mut Vec<u8>, v: f32| data.extend(if big_endian { v.to_be_bytes() } else { v.to_le_bytes() });
+        let body = mesh_body();
+        for vertex in body.0 {
+            for v in &vertex[..3] {
+                float(&mut data, *v);
+            }
+            data.extend(vertex[3..].iter().map(|&c| c as u8));
+        }
+        for face in body.1 {
+            data.push(face.len() as u8);
+            for i in face {
+                data.extend(if big_endian { i.to_be_bytes() } else { i.to_le_bytes() });
+            }
+            data.push(7);
+        }
+        data
+    }
+
+    fn mesh_body() -> (Vec<[f32; 6]>, Vec<Vec<i32>>) {
+        let mut lines = ASCII.split("end_header\n").nth(1).unwrap().lines();
+        let vertices = lines.by_ref().take(5)
+            .map(|l| l.split(' ').map(|w| w.parse().unwrap()).collect::<Vec<f32>>().try_into().unwrap())
+            .collect();
+        let faces = lines.map(|l| {
+            let words = l.split(' ').map(|w| w.parse().unwrap()).collect::<Vec<i32>>();
+            words[1..=words[0] as usize].to_vec()
+        }).collect();
+        (vertices, faces)
+    }
+
+    #[test]
+    fn binary_matches_ascii() {
+        let ascii = parse(ASCII.as_bytes()).unwrap();
+        assert_eq!(parse(&binary(false)).unwrap(), ascii);
+        assert_eq!(parse(&binary(true)).unwrap(), ascii);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let data = binary(false);
+        assert!(parse(&data[..data.len() - 1]).is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n0\n").is_err());
+        assert!(parse(ASCII.replace("0 0 1 0 0 0\n", "").as_bytes()).is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\nproperty float y\nproperty float z\nend_header\n").is_err());
+    }
+
+    #[test]
+    fn vertex_only_files_are_point_clouds() {
+        let data = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nproperty float nx\nproperty float ny\nproperty float nz\nend_header\n0 0 0 0 0 1\n1 0 0 0 0 1\n1 1 0 0 1 0\n";
+        let mesh = parse(data.as_bytes()).unwrap();
+        assert_eq!(mesh.positions, [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]]);
+        assert_eq!(mesh.normals.unwrap()[2], [0.0, 1.0, 0.0]);
+        assert!(mesh.indices.is_empty());
+        // 有 face 元素但是个数为 0 也一样
+        let mesh = parse(ASCII.replace("element face 2", "element face 0").as_bytes()).unwrap();
+        assert_eq!((mesh.positions.len(), mesh.indices.len()), (5, 0));
+    }
+}
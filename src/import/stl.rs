@@ -0,0 +1,123 @@
+// STL，ASCII 和二进制。每个三角形的顶点单独存放，面法线不可靠（很多导出器写 0），
+// 这里只读位置，法线之后焊接顶点再生成
+use anyhow::{bail, Context};
+
+use super::ImportedMesh;
+
+// 二进制：80 字节文件头、u32 三角形数，每个三角形 50 字节
+const HEADER_SIZE: usize = 84;
+const TRIANGLE_SIZE: usize = 50;
+
+pub fn parse(data: &[u8]) -> anyhow::Result<ImportedMesh> {
+    // 二进制文件的文件头也可能以 "solid" 开头，先按大小判断
+    let count = data.get(80..HEADER_SIZE).map(|count| u32::from_le_bytes(count.try_into().unwrap()));
+    // 32 位平台上三角形数太大时会溢出
+    let binary_size = count.map(|count| (count as usize).checked_mul(TRIANGLE_SIZE).and_then(|size| size.checked_add(HEADER_SIZE)));
+    let mesh = if binary_size == Some(Some(data.len())) {
+        parse_binary(data)
+    } else if data.starts_with(b"solid") {
+        parse_ascii(std::str::from_utf8(data).context("ASCII file is not UTF-8")?)?
+    } else if let (Some(count), Some(None)) = (count, binary_size) {
+        bail!("binary file with {} triangles is too large", count);
+    } else {
+        bail!("neither an ASCII nor a binary STL file");
+    };
+    if mesh.indices.is_empty() {
+        bail!("no triangles");
+    }
+    Ok(mesh)
+}
+
+fn parse_binary(data: &[u8]) -> ImportedMesh {
+    let positions = data[HEADER_SIZE..].chunks_exact(TRIANGLE_SIZE)
+        .flat_map(|triangle| (0..3).map(move |corner| {
+            let start = 12 + corner * 12;
+            [0, 4, 8].map(|offset| f32::from_le_bytes(triangle[start + offset..start + offset + 4].try_into().unwrap()))
+        }))
+        .collect::<Vec<_>>();
+    let name = String::from_utf8_lossy(&data[..80]).trim_end_matches('\0').trim().to_owned();
+    mesh(name, positions)
+}
+
+fn parse_ascii(text: &str) -> anyhow::Result<ImportedMesh> {
+    let mut lines = text.lines().map(str::trim);
+    let name = lines.next().unwrap_or_default().trim_start_matches("solid").trim().to_owned();
+    let mut positions = Vec::new();
+    for line in lines {
+        let mut words = line.split_whitespace();
+        if words.next() == Some("vertex") {
+            let mut position = [0.0; 3];
+            for p in &mut position {
+                let word = words.next().with_context(|| format!("invalid vertex line {:?}", line))?;
+                *p = word.parse().with_context(|| format!("invalid number {}", word))?;
+            }
+            positions.push(position);
+        }
+    }
+    if positions.len() % 3 != 0 {
+        bail!("{} vertices do not make whole triangles", positions.len());
+    }
+    Ok(mesh(name, positions))
+}
+
+fn mesh(name: String, positions: Vec<[f32; 3]>) -> ImportedMesh {
+    let indices = (0..positions.len() as u32).collect();
+    ImportedMesh { name, positions, indices, ..Default::default() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    const ASCII: &str = "solid part
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid part
+";
+
+    fn binary(header: &[u8]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, 0);
+        data.extend(2u32.to_le_bytes());
+        for triangle in [[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]], [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]]] {
+            data.extend([0.0f32; 3].iter().flat_map(|v| v.to_le_bytes()));
+            for corner in triangle {
+                data.extend(corner.iter().flat_map(|v: &f32| v.to_le_bytes()));
+            }
+            data.extend([0, 0]);
+        }
+        data
+    }
+
+    #[test]
+    fn ascii_and_binary_agree() {
+        let ascii = parse(ASCII.as_bytes()).unwrap();
+        assert_eq!(ascii.name, "part");
+        assert_eq!(ascii.positions.len(), 6);
+        assert_eq!(ascii.positions[5], [0.0, 1.0, 0.0]);
+        assert_eq!(ascii.indices, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(parse(&binary(b"part")).unwrap(), ascii);
+        // 文件头以 solid 开头的二进制文件
+        assert_eq!(parse(&binary(b"solid part")).unwrap().positions, ascii.positions);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let data = binary(b"part");
+        assert!(parse(&data[..data.len() - 1]).is_err());
+        assert!(parse(ASCII.replace("vertex 0 1 0", "vertex 0 1").as_bytes()).is_err());
+        assert!(parse(b"solid empty\nendsolid empty\n").is_err());
+    }
+}
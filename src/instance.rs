@@ -1,23 +1,73 @@
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
-    pub rotation: cgmath::Quaternion<f32>
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+    pub style: InstanceStyle
 }
+
+// 每个实例单独的外观参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstanceStyle {
+    // 乘到材质颜色上，包括 alpha
+    pub tint: [f32; 4],
+    // 用模型的另一个材质画，包括贴图、参数和 alpha 模式，None 表示用网格自己的材质
+    pub material: Option<u32>,
+    // 额外的自发光强度，按物体颜色叠加
+    pub emissive: f32
+}
+
+impl Default for InstanceStyle {
+    fn default() -> Self {
+        Self { tint: [1.0; 4], material: None, emissive: 0.0 }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
-    normal: [[f32; 3]; 3]
+    normal: [[f32; 3]; 3],
+    tint: [f32; 4],
+    material: u32,
+    emissive: f32
 }
 
+// InstanceRaw.material 中表示“不覆盖材质”的值
+pub const NO_MATERIAL_OVERRIDE: u32 = u32::MAX;
+
 impl Instance {
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
     pub fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw { 
-            model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
-            normal: cgmath::Matrix3::from(self.rotation).into()
-        }
+        InstanceRaw::new(self.model_matrix(), &self.style)
     }
 }
 impl InstanceRaw {
+    // normal 取模型矩阵左上 3x3 的逆转置，这样非等比缩放下法线依然正确
+    pub fn new(model: cgmath::Matrix4<f32>, style: &InstanceStyle) -> Self {
+        use cgmath::{Matrix, SquareMatrix};
+        let linear = cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal = linear.invert().map(|m| m.transpose()).unwrap_or_else(cgmath::Matrix3::identity);
+        InstanceRaw {
+            model: model.into(),
+            normal: normal.into(),
+            tint: style.tint,
+            material: style.material.unwrap_or(NO_MATERIAL_OVERRIDE),
+            emissive: style.emissive
+        }
+    }
+
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.model.into()
+    }
+
+    pub fn material_override(&self) -> Option<usize> {
+        (self.material != NO_MATERIAL_OVERRIDE).then_some(self.material as usize)
+    }
+
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
         wgpu::VertexBufferLayout { 
@@ -63,7 +113,109 @@ impl InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // tint
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // material override
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 29]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                // emissive
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 30]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ]
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, Rotation3, SquareMatrix, Vector3};
+
+    use super::{InstanceRaw, InstanceStyle, NO_MATERIAL_OVERRIDE};
+
+    fn transformed_normal(model: Matrix4<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+        let raw = InstanceRaw::new(model, &InstanceStyle::default());
+        (Matrix3::from(raw.normal) * normal).normalize()
+    }
+
+    fn assert_close(actual: Vector3<f32>, expected: Vector3<f32>) {
+        assert!((actual - expected).magnitude() < 1e-5, "{:?} != {:?}", actual, expected);
+    }
+
+    fn models() -> Vec<Matrix4<f32>> {
+        let rotation = Matrix4::from(Quaternion::from_axis_angle(Vector3::new(1.0, 2.0, 3.0).normalize(), Deg(40.0)));
+        vec![
+            Matrix4::from_nonuniform_scale(2.0, 1.0, 0.5),
+            Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0),
+            Matrix4::from_nonuniform_scale(3.0, -0.5, 2.0),
+            Matrix4::from_translation(Vector3::new(1.0, -2.0, 3.0)) * rotation * Matrix4::from_nonuniform_scale(0.25, 4.0, -1.5)
+        ]
+    }
+
+    // 斜放的平面：法线要和变换后的两条切线都垂直，朝向与变换后的切线叉积一致
+    #[test]
+    fn plane_normals_under_nonuniform_and_negative_scale() {
+        let (t1, t2) = (Vector3::new(1.0, 0.0, 1.0), Vector3::new(0.0, 1.0, 0.0));
+        let normal = t1.cross(t2).normalize();
+        for model in models() {
+            let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+            let expected = linear.invert().unwrap().transpose() * normal;
+            let actual = transformed_normal(model, normal);
+            assert_close(actual, expected.normalize());
+            let (w1, w2) = (linear * t1, linear * t2);
+            assert!(actual.dot(w1).abs() < 1e-5 && actual.dot(w2).abs() < 1e-5);
+            // 行列式为负时变换后的切线叉积方向反了
+            let winding = w1.cross(w2) * linear.determinant().signum();
+            assert_close(actual, winding.normalize());
+        }
+    }
+
+    // 缩放后的球是椭球，法线是隐式方程的梯度
+    #[test]
+    fn sphere_normals_under_nonuniform_and_negative_scale() {
+        for (sx, sy, sz) in [(2.0, 1.0, 0.5), (-1.0, 1.0, 1.0), (3.0, -0.5, 2.0)] {
+            let model = Matrix4::from_nonuniform_scale(sx, sy, sz);
+            for p in [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.6, 0.8, 0.0), Vector3::new(-0.48, 0.6, 0.64)] {
+                let gradient = Vector3::new(p.x / sx, p.y / sy, p.z / sz).normalize();
+                assert_close(transformed_normal(model, p), gradient);
+            }
+        }
+    }
+
+    // 按 desc 里的偏移读出某个插槽的字节
+    fn attribute_bytes(raw: &InstanceRaw, location: u32) -> Vec<u8> {
+        let desc = InstanceRaw::desc();
+        let attribute = desc.attributes.iter().find(|a| a.shader_location == location).unwrap();
+        let offset = attribute.offset as usize;
+        bytemuck::bytes_of(raw)[offset..offset + attribute.format.size() as usize].to_vec()
+    }
+
+    #[test]
+    fn style_is_packed_at_locations_12_to_14() {
+        let style = InstanceStyle { tint: [0.25, 0.5, 0.75, 0.4], material: Some(3), emissive: 1.5 };
+        let raw = InstanceRaw::new(Matrix4::from_scale(2.0), &style);
+        let tint: [f32; 4] = bytemuck::pod_read_unaligned(&attribute_bytes(&raw, 12));
+        let material: u32 = bytemuck::pod_read_unaligned(&attribute_bytes(&raw, 13));
+        let emissive: f32 = bytemuck::pod_read_unaligned(&attribute_bytes(&raw, 14));
+        assert_eq!((tint, material, emissive), (style.tint, 3, 1.5));
+
+        let raw = InstanceRaw::new(Matrix4::from_scale(2.0), &InstanceStyle::default());
+        let tint: [f32; 4] = bytemuck::pod_read_unaligned(&attribute_bytes(&raw, 12));
+        let material: u32 = bytemuck::pod_read_unaligned(&attribute_bytes(&raw, 13));
+        let emissive: f32 = bytemuck::pod_read_unaligned(&attribute_bytes(&raw, 14));
+        assert_eq!((tint, material, emissive), ([1.0; 4], NO_MATERIAL_OVERRIDE, 0.0));
+        // 样式在矩阵之后，没有被覆盖
+        let model: [f32; 4] = bytemuck::pod_read_unaligned(&attribute_bytes(&raw, 5));
+        assert_eq!(model, [2.0, 0.0, 0.0, 0.0]);
+        assert_eq!(std::mem::size_of::<InstanceRaw>(), InstanceRaw::desc().array_stride as usize);
+    }
+}
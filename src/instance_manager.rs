@@ -0,0 +1,259 @@
+use std::ops::Range;
+
+use crate::instance::InstanceRaw;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceHandle {
+    slot: u32,
+    generation: u32
+}
+
+struct Slot {
+    generation: u32,
+    // 在紧密排列的实例数组中的下标，槽空闲时为 None
+    index: Option<usize>
+}
+
+// 实例数据在 CPU 和 GPU 上都是紧密排列的，删除时把最后一个实例搬到空位上，
+// 所以 draw 时始终是 0..len。这里只管 CPU 端，GPU 缓冲在 InstanceBuffer 里
+#[derive(Default)]
+pub struct InstanceManager {
+    data: Vec<InstanceRaw>,
+    // 紧密下标 -> 槽
+    owners: Vec<u32>,
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>,
+    dirty: Option<Range<usize>>
+}
+
+impl InstanceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(range) => range.start.min(index)..range.end.max(index + 1),
+            None => index..index + 1
+        });
+    }
+
+    fn index_of(&self, handle: InstanceHandle) -> Option<usize> {
+        let slot = self.slots.get(handle.slot as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.index
+    }
+
+    pub fn insert(&mut self, raw: InstanceRaw) -> InstanceHandle {
+        let index = self.data.len();
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                self.slots[slot as usize].index = Some(index);
+                slot
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, index: Some(index) });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        self.data.push(raw);
+        self.owners.push(slot);
+        self.mark_dirty(index);
+        InstanceHandle { slot, generation: self.slots[slot as usize].generation }
+    }
+
+    pub fn remove(&mut self, handle: InstanceHandle) -> bool {
+        let index = match self.index_of(handle) {
+            Some(index) => index,
+            None => return false
+        };
+        let slot = &mut self.slots[handle.slot as usize];
+        slot.index = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(handle.slot);
+
+        self.data.swap_remove(index);
+        self.owners.swap_remove(index);
+        if index < self.data.len() {
+            // 最后一个实例被搬到了 index
+            self.slots[self.owners[index] as usize].index = Some(index);
+            self.mark_dirty(index);
+        }
+        if let Some(range) = self.dirty.as_mut() {
+            range.end = range.end.min(self.data.len());
+            if range.start >= range.end {
+                self.dirty = None;
+            }
+        }
+        true
+    }
+
+    pub fn update(&mut self, handle: InstanceHandle, raw: InstanceRaw) -> bool {
+        match self.index_of(handle) {
+            Some(index) => {
+                self.data[index] = raw;
+                self.mark_dirty(index);
+                true
+            }
+            None => false
+        }
+    }
+
+    // 画在 `index` 处的实例的句柄，比如拾取返回的实例下标
+    pub fn handle_at(&self, index: usize) -> Option<InstanceHandle> {
+        let slot = *self.owners.get(index)?;
+        Some(InstanceHandle { slot, generation: self.slots[slot as usize].generation })
+    }
+
+    pub fn instances(&self) -> &[InstanceRaw] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    // 上次调用以来有变化的实例，用一个覆盖全部的范围表示
+    pub fn take_dirty(&mut self) -> Option<Range<usize>> {
+        self.dirty.take()
+    }
+}
+
+pub struct InstanceBuffer {
+    label: String,
+    buffer: wgpu::Buffer,
+    capacity: usize
+}
+
+impl InstanceBuffer {
+    const MIN_CAPACITY: usize = 16;
+
+    pub fn new(device: &wgpu::Device, label: &str) -> Self {
+        Self {
+            label: label.to_owned(),
+            buffer: Self::create_buffer(device, label, Self::MIN_CAPACITY),
+            capacity: Self::MIN_CAPACITY
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        })
+    }
+
+    // 容量按两倍增长，直到能放下 len 个实例
+    fn grown_capacity(capacity: usize, len: usize) -> usize {
+        let mut capacity = capacity;
+        while capacity < len {
+            capacity *= 2;
+        }
+        capacity
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    // 需要时按两倍扩大缓冲，否则只写入有变化的范围
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &mut InstanceManager) {
+        let mut dirty = instances.take_dirty();
+        if instances.len() > self.capacity {
+            self.capacity = Self::grown_capacity(self.capacity, instances.len());
+            self.buffer = Self::create_buffer(device, &self.label, self.capacity);
+            dirty = Some(0..instances.len());
+        }
+        if let Some(range) = dirty {
+            let offset = (range.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+            queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(&instances.instances()[range]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstanceBuffer, InstanceManager};
+    use crate::instance::{InstanceRaw, InstanceStyle};
+
+    fn raw(x: f32) -> InstanceRaw {
+        InstanceRaw::new(cgmath::Matrix4::from_translation(cgmath::Vector3::new(x, 0.0, 0.0)), &InstanceStyle::default())
+    }
+
+    fn xs(instances: &InstanceManager) -> Vec<f32> {
+        instances.instances().iter().map(|i| i.model_matrix().w.x).collect()
+    }
+
+    #[test]
+    fn stale_handles_are_rejected() {
+        let mut instances = InstanceManager::new();
+        let a = instances.insert(raw(0.0));
+        assert!(instances.remove(a));
+        assert!(!instances.remove(a));
+        // 新实例复用 a 的槽，但代数不同
+        let b = instances.insert(raw(1.0));
+        assert_ne!(a, b);
+        assert!(!instances.update(a, raw(2.0)));
+        assert!(!instances.remove(a));
+        assert_eq!(xs(&instances), [1.0]);
+        assert_eq!(instances.handle_at(0), Some(b));
+        assert!(instances.update(b, raw(3.0)));
+        assert_eq!(xs(&instances), [3.0]);
+    }
+
+    #[test]
+    fn swap_remove_repoints_the_moved_instance() {
+        let mut instances = InstanceManager::new();
+        let handles = (0..4).map(|i| instances.insert(raw(i as f32))).collect::<Vec<_>>();
+        assert!(instances.remove(handles[1]));
+        // 最后一个实例搬到了 1
+        assert_eq!(xs(&instances), [0.0, 3.0, 2.0]);
+        assert_eq!(instances.handle_at(1), Some(handles[3]));
+        assert!(instances.update(handles[3], raw(5.0)));
+        assert_eq!(xs(&instances), [0.0, 5.0, 2.0]);
+        // 删除最后一个不需要搬动
+        assert!(instances.remove(handles[2]));
+        assert_eq!(xs(&instances), [0.0, 5.0]);
+        assert_eq!(instances.handle_at(2), None);
+        assert!(instances.remove(handles[3]));
+        assert!(instances.remove(handles[0]));
+        assert_eq!(instances.len(), 0);
+    }
+
+    #[test]
+    fn dirty_ranges_merge_and_trim() {
+        let mut instances = InstanceManager::new();
+        let handles = (0..6).map(|i| instances.insert(raw(i as f32))).collect::<Vec<_>>();
+        assert_eq!(instances.take_dirty(), Some(0..6));
+        assert_eq!(instances.take_dirty(), None);
+
+        instances.update(handles[4], raw(0.0));
+        instances.update(handles[1], raw(0.0));
+        assert_eq!(instances.take_dirty(), Some(1..5));
+
+        // 被删掉的尾部不用再上传
+        instances.update(handles[5], raw(0.0));
+        instances.remove(handles[5]);
+        assert_eq!(instances.take_dirty(), None);
+        instances.update(handles[2], raw(0.0));
+        instances.update(handles[4], raw(0.0));
+        instances.remove(handles[4]);
+        assert_eq!(instances.take_dirty(), Some(2..4));
+        // 搬动的实例是脏的
+        instances.remove(handles[0]);
+        assert_eq!(instances.take_dirty(), Some(0..1));
+    }
+
+    #[test]
+    fn capacity_doubles() {
+        assert_eq!(InstanceBuffer::grown_capacity(16, 0), 16);
+        assert_eq!(InstanceBuffer::grown_capacity(16, 16), 16);
+        assert_eq!(InstanceBuffer::grown_capacity(16, 17), 32);
+        assert_eq!(InstanceBuffer::grown_capacity(16, 100), 128);
+        assert_eq!(InstanceBuffer::grown_capacity(32, 33), 64);
+    }
+}
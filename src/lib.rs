@@ -1,32 +1,65 @@
+use std::rc::Rc;
+
 mod texture;
 mod vertex;
+mod assets;
+mod bake;
 mod camera;
 mod camera_controller;
+mod debug;
+mod error;
+mod geometry;
 mod global;
+mod import;
 mod instance;
+mod instance_manager;
 mod resources;
 mod model;
+mod pack;
 mod light;
+mod loader;
+mod picking;
+mod pipeline;
+mod ray;
+mod scene;
+mod shaders;
+mod vfs;
 
 use camera::{Camera, CameraUniform, Projection};
 use camera_controller::CameraController;
-use instance::Instance;
+use debug::DebugRenderer;
+use instance::{Instance, InstanceStyle};
+use instance_manager::{InstanceBuffer, InstanceHandle, InstanceManager};
+use assets::{Assets, Handle};
 use light::DrawLight;
-use model::{DrawModel, Model};
+use loader::AssetLoader;
+use model::{AlphaMode, DrawModel, InstanceRun, MaterialPipelines, Model};
+use picking::{Picker, PickResult};
+use pipeline::{PipelineBuilder, PipelineCache};
+use scene::{NodeId, Scene, Transform};
+use shaders::ShaderLibrary;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 use wgpu::util::DeviceExt;
-use vertex::Vertex;
+use vertex::{PositionVertex, Vertex, VertexLayout};
 use cgmath::prelude::*;
 
-use crate::{instance::InstanceRaw, texture::Texture, model::{ModelVertex, Mesh}, light::PointLightUniform};
+use crate::{instance::InstanceRaw, texture::Texture, model::{ModelVertex, Mesh, MeshData}, light::PointLightUniform};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+// 给 src/bin/bake.rs 用
+pub use bake::{bake, baked_name};
+pub use error::Error;
+pub use resources::MeshProcessing;
+pub use pack::{write as write_pack, Pack};
+// 嵌入程序的应用可以在 run 之前换成自己的资源根
+pub use vfs::{set as set_resource_roots, Root as ResourceRoot, Vfs};
+
 // // 顶点数据
 // const VERTICES: &[Vertex] = &[
 //     Vertex { position: [-0.0868241, 0.49240386, 0.0], tex_coords: [0.4131759, 0.00759614], }, // A
@@ -41,8 +74,8 @@ use wasm_bindgen::prelude::*;
 //     1, 2, 4,
 //     2, 3, 4,
 // ];
-// 物体旋转速度
-const ROTATION_SPEED: f32 = 1.0 * std::f32::consts::PI / 60.0;
+// 开启多重采样时的采样数，适配器不支持时退回 1
+const MSAA_SAMPLE_COUNT: u32 = 4;
 
 struct State {
     surface: wgpu::Surface,
@@ -51,7 +84,25 @@ struct State {
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     clear_color: wgpu::Color,
-    render_pipeline: wgpu::RenderPipeline,
+    // obj_model 每个材质对应的管线
+    material_pipelines: Vec<MaterialPipelines>,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    pipelines: PipelineCache,
+    shaders: ShaderLibrary,
+    // 预处理后的 shader.wgsl、light.wgsl、debug.wgsl 和 picking.wgsl
+    material_shader: String,
+    light_shader: String,
+    debug_shader: String,
+    pick_shader: String,
+    debug: DebugRenderer,
+    // 当前的多重采样数，1 表示关闭，按 K 切换
+    sample_count: u32,
+    // 适配器支持时是 MSAA_SAMPLE_COUNT，否则是 1
+    max_sample_count: u32,
+    // 开启多重采样时，Mask 材质的边缘用 alpha-to-coverage 抗锯齿，按 C 切换
+    alpha_to_coverage: bool,
+    // sample_count > 1 时先画到这里，再 resolve 到 surface
+    msaa_view: Option<wgpu::TextureView>,
     // vertex_buffer: wgpu::Buffer,
     // index_buffer: wgpu::Buffer,
     // num_indices: u32,
@@ -64,25 +115,42 @@ struct State {
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
+    scene: Scene,
+    light_pivot_node: NodeId,
+    light_node: NodeId,
+    instances: InstanceManager,
+    instance_buffer: InstanceBuffer,
+    // 运行时生成的、不属于场景节点的实例
+    spawned: Vec<(InstanceHandle, Instance)>,
     depth_texture: texture::Texture,
-    obj_model: Model,
+    // 加载完成前是占位模型
+    obj_model: Handle<Model>,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    // 上传模型时属性流的格式
+    vertex_layout: VertexLayout,
+    assets: Assets,
+    loader: AssetLoader,
     light_uniform: PointLightUniform,
     light_buffer: wgpu::Buffer,
     light_bind_group: wgpu::BindGroup,
-    light_render_pipeline: wgpu::RenderPipeline,
-    light_mesh: Mesh
+    light_render_pipeline: Rc<wgpu::RenderPipeline>,
+    light_pipeline_layout: wgpu::PipelineLayout,
+    light_mesh: Mesh,
+    picker: Picker,
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    // true 时用 CPU 射线检测拾取，否则读回 GPU 渲染的 id
+    cpu_picking: bool,
+    selection: Option<(InstanceHandle, PickResult)>
 }
 
 impl State {
-    async fn new(window: &Window) -> Self {
+    async fn new(window: &Window) -> Result<Self, Error> {
         let size = window.inner_size();
 
         // Instance
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor { backends: wgpu::Backends::all(), ..Default::default() });
         // Surface
-        let surface = unsafe { instance.create_surface(window).unwrap() };
+        let surface = unsafe { instance.create_surface(window)? };
         // Adapter
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -91,12 +159,13 @@ impl State {
                 compatible_surface: Some(&surface),
             })
             .await
-            .unwrap();
+            .ok_or(Error::NoAdapter)?;
         // Device, Queue
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    // 线框调试模式用，不支持时退回重心坐标的画法
+                    features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
                     limits: if cfg!(target_arch = "wasm32") {
                         wgpu::Limits::downlevel_webgl2_defaults()
                     } else {
@@ -106,8 +175,7 @@ impl State {
                 },
                 None,
             )
-            .await
-            .unwrap();
+            .await?;
         // SurfaceConfiguration
         let caps = surface.get_capabilities(&adapter);
         let config = wgpu::SurfaceConfiguration {
@@ -157,6 +225,17 @@ impl State {
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None
+                },
+                // material
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
                 }
             ],
             label: Some("texture_bind_group_layout")
@@ -255,11 +334,23 @@ impl State {
             }] 
         });
 
+        // Scene
+        let mut scene = Scene::new();
+        // 灯光挂在一个绕 y 轴旋转的支点下面
+        let light_pivot_node = scene.add_node("light_pivot", None, Transform::default(), None);
+        let light_node = scene.add_node(
+            "light",
+            Some(light_pivot_node),
+            Transform::from_translation(cgmath::Vector3::from(light_uniform.position)),
+            None
+        );
+
         // instances
         const SPACE_BETWEEN: f32 = 3.0;
         const NUM_INSTANCES_PER_ROW: u32 = 10;
         const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
         
+        let grid_node = scene.add_node("grid", None, Transform::default(), None);
         let instances = (0..NUM_INSTANCES_PER_ROW).flat_map(|z| {
             (0..NUM_INSTANCES_PER_ROW).map(move |x| {
                 let position = SPACE_BETWEEN * (cgmath::Vector3::new(x as f32, 0.0, z as f32) - INSTANCE_DISPLACEMENT);
@@ -270,68 +361,71 @@ impl State {
                 };
                 Instance {
                     position,
-                    rotation
+                    rotation,
+                    scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+                    style: InstanceStyle::default()
                 }
             })
         }).collect::<Vec<_>>();
+        for (i, instance) in instances.iter().enumerate() {
+            scene.add_node(&format!("cube_{}", i), Some(grid_node), Transform::from(instance), Some(0));
+        }
+        scene.update_world_matrices();
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST
-            }
-        );
+        let mut instances = InstanceManager::new();
+        scene.sync_instances(0, &mut instances);
+        let mut instance_buffer = InstanceBuffer::new(&device, "Instance Buffer");
+        instance_buffer.upload(&device, &queue, &mut instances);
 
         // Depth Texture
-        let depth_texture: Texture = texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+        let max_sample_count = if adapter.get_texture_format_features(config.format).flags.sample_count_supported(MSAA_SAMPLE_COUNT) {
+            MSAA_SAMPLE_COUNT
+        } else {
+            1
+        };
+        let sample_count = max_sample_count;
+        let alpha_to_coverage = true;
+        let msaa_view = create_msaa_view(&device, &config, sample_count);
+        let depth_texture: Texture = texture::Texture::create_depth_texture(&device, &config, sample_count, "depth_texture");
+        let multisample = wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false
+        };
         
         // Render Pipeline
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into())
-            };
-            let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &camera_bind_group_layout,
-                    &light_bind_group_layout
-                ],
-                push_constant_ranges: &[]
-            });
-            create_render_pipeline(
-                &device, 
-                &render_pipeline_layout, 
-                config.format, 
-                Some(Texture::DEPTH_FORMAT), 
-                &[ModelVertex::desc(), InstanceRaw::desc()],
-                shader
-            )
-        };
+        let mut pipelines = PipelineCache::new();
+        let shaders = ShaderLibrary::new();
+        let compose = |name: &str, defines| shaders.compose(name, defines)
+            .map_err(|e| Error::Shader { file: name.to_owned(), message: format!("{:#}", e) });
+        let material_shader = compose("shader.wgsl", MATERIAL_SHADER_DEFINES)?;
+        let light_shader = compose("light/light.wgsl", LIGHT_SHADER_DEFINES)?;
+        let debug_shader = compose("debug/debug.wgsl", &[])?;
+        let pick_shader = compose("picking/picking.wgsl", &[])?;
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                &light_bind_group_layout
+            ],
+            push_constant_ranges: &[]
+        });
 
         // Light Render 
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor { 
-                label: Some("Light Pipeline Layout"), 
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[] 
-            });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("light/light.wgsl").into())
-            };
-            create_render_pipeline(
-                &device, 
-                &layout, 
-                config.format, 
-                Some(Texture::DEPTH_FORMAT), 
-                &[ModelVertex::desc()], 
-                shader
-            )
-        };
+        let light_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor { 
+            label: Some("Light Pipeline Layout"), 
+            bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+            push_constant_ranges: &[] 
+        });
+        let light_render_pipeline = create_light_pipeline(
+            &device,
+            &mut pipelines,
+            &light_pipeline_layout,
+            &light_shader,
+            config.format,
+            multisample
+        );
 
         // Clear Color
         let clear_color = wgpu::Color::BLACK;
@@ -353,8 +447,23 @@ impl State {
         // // indices Number
         // let num_indices = INDICES.len() as u32;
 
-        // load obj
-        let obj_model = resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout).await.unwrap();
+        // load obj，在后台加载，完成前画占位模型
+        let mut assets = Assets::new();
+        let vertex_layout = VertexLayout::from_env();
+        log::info!("vertex layout: {:?}", vertex_layout);
+        let placeholder = resources::placeholder_model().upload(&device, &queue, "placeholder", &texture_bind_group_layout, vertex_layout, &mut assets)?;
+        let obj_model = assets.insert_model("placeholder", placeholder);
+        let mut loader = AssetLoader::new();
+        loader.load_model("cube.obj", resources::MeshProcessing::default());
+        let material_pipelines = create_material_pipelines(
+            &device,
+            &mut pipelines,
+            &render_pipeline_layout,
+            &material_shader,
+            config.format,
+            wgpu::MultisampleState { alpha_to_coverage_enabled: alpha_to_coverage, ..multisample },
+            &obj_model
+        );
 
         // light mesh
         let light_vertices: &[ModelVertex] = &[
@@ -387,32 +496,38 @@ impl State {
             0, 3, 7,   // 三角面11
             7, 4, 0,   // 三角面12
         ];
-        let light_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("default_light_vertex"),
-            contents: bytemuck::cast_slice(light_vertices),
-            usage: wgpu::BufferUsages::VERTEX
-        });
-        let light_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("default_light_indices"),
-            contents: bytemuck::cast_slice(light_indices),
-            usage: wgpu::BufferUsages::INDEX
-        });
-        println!("index length {}",light_indices.len());
-        let light_mesh = Mesh {
+
+        // Picking
+        let picker = Picker::new(&device, &config, &camera_bind_group_layout, &pick_shader, &mut pipelines);
+        let debug = DebugRenderer::new(&device, &camera_bind_group_layout, config.format, multisample);
+        // 光源的着色器只用位置，不上传其他属性
+        let light_mesh = Mesh::from_data(&device, MeshData {
             name: "light_mesh".to_owned(),
-            vertex_buffer: light_vertex_buffer,
-            index_buffer: light_index_buffer,
-            num_elements: light_indices.len() as u32,
+            vertices: light_vertices.to_vec(),
+            indices: light_indices.iter().map(|&i| i as u32).collect(),
+            index_format: wgpu::IndexFormat::Uint16,
             material: 0
-        };
-        Self {
+        }, None);
+        Ok(Self {
             surface,
             device,
             queue,
             config,
             size,
             clear_color,
-            render_pipeline,
+            material_pipelines,
+            render_pipeline_layout,
+            pipelines,
+            shaders,
+            material_shader,
+            light_shader,
+            debug_shader,
+            pick_shader,
+            debug,
+            sample_count,
+            max_sample_count,
+            alpha_to_coverage,
+            msaa_view,
             // vertex_buffer,
             // index_buffer,
             // num_indices,
@@ -424,17 +539,30 @@ impl State {
             camera_uniform,
             camera_buffer,
             camera_bind_group,
+            scene,
+            light_pivot_node,
+            light_node,
             instances,
             instance_buffer,
+            spawned: Vec::new(),
             depth_texture,
             obj_model,
+            texture_bind_group_layout,
+            vertex_layout,
+            assets,
+            loader,
             light_uniform,
             light_buffer,
             light_bind_group,
             light_render_pipeline,
+            light_pipeline_layout,
             light_mesh,
+            picker,
+            cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            cpu_picking: false,
+            selection: None,
             mouse_pressed: false,
-        }
+        })
     }
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
@@ -443,11 +571,93 @@ impl State {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.msaa_view = create_msaa_view(&self.device, &self.config, self.sample_count);
+            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, self.sample_count, "depth_texture");
+            self.picker.resize(&self.device, &self.config);
         }
     }
     fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
+            WindowEvent::KeyboardInput { 
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::P),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                self.cpu_picking = !self.cpu_picking;
+                log::info!("cpu picking: {}", self.cpu_picking);
+                true
+            }
+            WindowEvent::KeyboardInput { 
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::M),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                self.debug.set_mode(self.debug.mode().next());
+                log::info!("debug mode: {:?}", self.debug.mode());
+                true
+            }
+            WindowEvent::KeyboardInput { 
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::V),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                log::info!("debug vectors: {}", self.debug.toggle_vectors());
+                true
+            }
+            WindowEvent::KeyboardInput { 
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::K),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                let sample_count = if self.sample_count > 1 { 1 } else { self.max_sample_count };
+                self.set_multisample(sample_count, self.alpha_to_coverage);
+                true
+            }
+            WindowEvent::KeyboardInput { 
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::C),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                self.set_multisample(self.sample_count, !self.alpha_to_coverage);
+                true
+            }
+            WindowEvent::KeyboardInput { 
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::N),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                self.spawn_instance();
+                true
+            }
+            WindowEvent::KeyboardInput { 
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::Delete),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                self.despawn_selected();
+                true
+            }
             WindowEvent::KeyboardInput { 
                 input: KeyboardInput {
                     virtual_keycode: Some(key),
@@ -471,6 +681,37 @@ impl State {
                 self.mouse_pressed = *state == ElementState::Pressed;
                 true
             }
+            WindowEvent::MouseInput { 
+                button: MouseButton::Right,
+                state: ElementState::Pressed,
+                ..
+            } => {
+                if self.cpu_picking {
+                    let ray = self.camera.screen_to_ray(
+                        self.cursor_position.x as f32,
+                        self.cursor_position.y as f32,
+                        self.size,
+                        &self.projection
+                    );
+                    let result = picking::pick_instances(
+                        &ray,
+                        &self.obj_model,
+                        self.instances.instances().iter().map(InstanceRaw::model_matrix)
+                    );
+                    self.set_selection(result);
+                } else {
+                    let view_proj = self.projection.calc_matrix() * self.camera.calc_matrix();
+                    self.picker.request(self.cursor_position.x as u32, self.cursor_position.y as u32, view_proj);
+                }
+                true
+            }
+            WindowEvent::CursorMoved { 
+                position,
+                ..
+            } => {
+                self.cursor_position = *position;
+                true
+            }
             _ => false
         }
     }
@@ -478,24 +719,273 @@ impl State {
         self.camera_controller.update_camera(&mut self.camera, dt);
         self.camera_uniform.update_view_proj(&self.camera, &self.projection);
         self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
-        // update instances rotation
-        // self.update_instances();
         self.update_light(dt);
-    }
-    fn update_light(&mut self, dt: instant::Duration){
-        let old_position = cgmath::Vector3::from(self.light_uniform.position);
-        self.light_uniform.position = (cgmath::Quaternion::from_angle_y(cgmath::Deg(60.0 * dt.as_secs_f32())).rotate_vector(old_position)).into();
+        self.scene.update_world_matrices();
+        self.scene.sync_instances(0, &mut self.instances);
+        self.instance_buffer.upload(&self.device, &self.queue, &mut self.instances);
+        self.light_uniform.position = self.scene.node(self.light_node).world_matrix().w.truncate().into();
         self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light_uniform]));
+        self.update_selection();
+        self.update_assets();
+        self.reload_shaders();
+        self.debug.prepare(
+            &self.device,
+            &self.queue,
+            &mut self.pipelines,
+            &self.obj_model,
+            &self.projection,
+            &self.debug_shader
+        );
+    }
+    // 把后台加载完的模型上传到 GPU，替换占位模型
+    fn update_assets(&mut self) {
+        let changes = self.loader.poll_changes();
+        for file_name in &changes.files {
+            log::info!("{} changed", file_name);
+            self.assets.forget(file_name);
+        }
+        for file_name in &changes.models {
+            // 模型本身没变、只有贴图变了时也要重新上传，材质的 bind group 才会用上新贴图
+            self.assets.forget(file_name);
+            log::info!("reloading {}", file_name);
+        }
+        let previous = self.loader.progress();
+        let multisample = self.material_multisample();
+        for (file_name, result) in self.loader.poll() {
+            let data = match result {
+                Ok(data) => data,
+                Err(e) => {
+                    // 热加载失败时继续用旧的模型
+                    log::error!("failed to load {}: {}", file_name, e.report());
+                    continue;
+                }
+            };
+            // 同一个模型被加载了两次时沿用已经上传的那份
+            let model = match self.assets.model(&file_name) {
+                Some(model) => model,
+                None => match data.upload(&self.device, &self.queue, &file_name, &self.texture_bind_group_layout, self.vertex_layout, &mut self.assets) {
+                    Ok(model) => self.assets.insert_model(&file_name, model),
+                    Err(e) => {
+                        log::error!("failed to load {}: {}", file_name, e.report());
+                        continue;
+                    }
+                }
+            };
+            if Handle::ptr_eq(&model, &self.obj_model) {
+                continue;
+            }
+            self.material_pipelines = create_material_pipelines(
+                &self.device,
+                &mut self.pipelines,
+                &self.render_pipeline_layout,
+                &self.material_shader,
+                self.config.format,
+                multisample,
+                &model
+            );
+            // 占位模型和它的贴图没有别的句柄，在这里释放
+            self.obj_model = model;
+            self.debug.model_changed();
+        }
+        let progress = self.loader.progress();
+        if progress != previous {
+            let (models, textures) = self.assets.len();
+            log::info!(
+                "assets: {} loaded, {} failed, {} pending, {} files, {} KiB; {} models and {} textures in use",
+                progress.loaded, progress.failed, progress.pending(), progress.files, progress.bytes / 1024, models, textures
+            );
+        }
+    }
+    fn multisample(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState { count: self.sample_count, mask: !0, alpha_to_coverage_enabled: false }
+    }
+    // 传给 create_material_pipelines，alpha_to_coverage_enabled 表示 Mask 材质是否使用
+    fn material_multisample(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState { alpha_to_coverage_enabled: self.alpha_to_coverage, ..self.multisample() }
+    }
+    // 改变多重采样设置，重建受影响的附件和管线，没变的管线从缓存里取
+    fn set_multisample(&mut self, sample_count: u32, alpha_to_coverage: bool) {
+        let samples_changed = sample_count != self.sample_count;
+        self.sample_count = sample_count;
+        self.alpha_to_coverage = alpha_to_coverage;
+        let (multisample, material_multisample) = (self.multisample(), self.material_multisample());
+        if samples_changed {
+            self.msaa_view = create_msaa_view(&self.device, &self.config, self.sample_count);
+            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, self.sample_count, "depth_texture");
+            self.light_render_pipeline = create_light_pipeline(
+                &self.device,
+                &mut self.pipelines,
+                &self.light_pipeline_layout,
+                &self.light_shader,
+                self.config.format,
+                multisample
+            );
+            self.debug.set_multisample(multisample);
+        }
+        // 只切换 alpha-to-coverage 时只有 Mask 材质的管线会变
+        self.material_pipelines = create_material_pipelines(
+            &self.device,
+            &mut self.pipelines,
+            &self.render_pipeline_layout,
+            &self.material_shader,
+            self.config.format,
+            material_multisample,
+            &self.obj_model
+        );
+        log::info!("msaa: {}x, alpha-to-coverage: {}", self.sample_count, self.alpha_to_coverage && self.sample_count > 1);
+    }
+    // 开发模式下着色器文件改动后重建管线，出错时保留原来的管线
+    fn reload_shaders(&mut self) {
+        let changes = self.shaders.poll();
+        if changes.is_empty() {
+            return;
+        }
+        let names = changes.iter().map(|c| c.name).collect::<Vec<_>>().join(", ");
+        match self.rebuild_pipelines() {
+            Ok(()) => log::info!("reloaded {}", names),
+            Err(e) => {
+                log::error!("failed to reload {}, keeping the previous pipelines\n{}", names, e);
+                for change in changes {
+                    self.shaders.revert(change);
+                }
+            }
+        }
+    }
+    // 被 include 的文件可能被任何着色器用到，所以全部重建
+    fn rebuild_pipelines(&mut self) -> anyhow::Result<()> {
+        let material_shader = self.shaders.compose("shader.wgsl", MATERIAL_SHADER_DEFINES)?;
+        let light_shader = self.shaders.compose("light/light.wgsl", LIGHT_SHADER_DEFINES)?;
+        let debug_shader = self.shaders.compose("debug/debug.wgsl", &[])?;
+        let pick_shader = self.shaders.compose("picking/picking.wgsl", &[])?;
+        let (multisample, material_multisample) = (self.multisample(), self.material_multisample());
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let material_pipelines = create_material_pipelines(
+            &self.device,
+            &mut self.pipelines,
+            &self.render_pipeline_layout,
+            &material_shader,
+            self.config.format,
+            material_multisample,
+            &self.obj_model
+        );
+        let light_render_pipeline = create_light_pipeline(
+            &self.device,
+            &mut self.pipelines,
+            &self.light_pipeline_layout,
+            &light_shader,
+            self.config.format,
+            multisample
+        );
+        let pick_pipeline = self.picker.build_pipeline(&self.device, &mut self.pipelines, &pick_shader);
+        // 调试管线按需创建，这里只重建当前模式用到的
+        self.debug.shader_changed();
+        self.debug.prepare(
+            &self.device,
+            &self.queue,
+            &mut self.pipelines,
+            &self.obj_model,
+            &self.projection,
+            &debug_shader
+        );
+        let error = pollster::block_on(self.device.pop_error_scope());
+        let shaders = [
+            (&mut self.material_shader, material_shader),
+            (&mut self.light_shader, light_shader),
+            (&mut self.debug_shader, debug_shader),
+            (&mut self.pick_shader, pick_shader)
+        ];
+        if let Some(e) = error {
+            // 没变的着色器和正在用的管线是同一份缓存，只删新建的
+            for (current, new) in shaders {
+                if new != *current {
+                    self.pipelines.remove_shader(&new);
+                }
+            }
+            // 下次 prepare 用原来的源码重建
+            self.debug.shader_changed();
+            return Err(anyhow::anyhow!("{}", e));
+        }
+        self.material_pipelines = material_pipelines;
+        self.light_render_pipeline = light_render_pipeline;
+        self.picker.set_pipeline(pick_pipeline);
+        for (current, new) in shaders {
+            let old = std::mem::replace(current, new);
+            if old != *current {
+                self.pipelines.remove_shader(&old);
+            }
+        }
+        Ok(())
     }
-    fn update_instances(&mut self){
-        self.instances.iter_mut().for_each(|i| {
-            let rad = cgmath::Rad(ROTATION_SPEED);
-            let amount = cgmath::Quaternion::from_angle_y(rad);
-            let current = i.rotation;
-            i.rotation = amount * current;
+    fn update_selection(&mut self) {
+        if let std::task::Poll::Ready(result) = self.picker.poll(&self.device, &self.obj_model) {
+            self.set_selection(result);
+        }
+    }
+    fn set_selection(&mut self, result: Option<PickResult>) {
+        if let Some((handle, _)) = self.selection.take() {
+            self.set_instance_style(handle, InstanceStyle::default());
+        }
+        self.selection = result.and_then(|hit| {
+            let handle = self.instances.handle_at(hit.instance)?;
+            let node = self.scene.node_by_instance(handle).map(|(_, n)| n.name.as_str());
+            log::info!("picked instance {} ({:?}) mesh {:?} at {:?}", hit.instance, node, hit.mesh, hit.position);
+            Some((handle, hit))
         });
-        let instance_data = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+        match &self.selection {
+            Some((handle, _)) => self.set_instance_style(*handle, InstanceStyle {
+                tint: [1.0, 0.85, 0.4, 1.0],
+                emissive: 0.3,
+                ..Default::default()
+            }),
+            None => log::info!("picked nothing")
+        }
+    }
+    fn set_instance_style(&mut self, handle: InstanceHandle, style: InstanceStyle) {
+        if let Some((node, _)) = self.scene.node_by_instance(handle) {
+            self.scene.update_style(node, |s| *s = style);
+        } else if let Some((_, instance)) = self.spawned.iter_mut().find(|(h, _)| *h == handle) {
+            instance.style = style;
+            self.instances.update(handle, instance.to_raw());
+        }
+    }
+    // 在镜头前方生成一个实例
+    fn spawn_instance(&mut self) {
+        let ray = self.camera.screen_to_ray(
+            self.size.width as f32 * 0.5,
+            self.size.height as f32 * 0.5,
+            self.size,
+            &self.projection
+        );
+        let instance = Instance {
+            position: cgmath::EuclideanSpace::to_vec(ray.at(5.0)),
+            rotation: cgmath::Quaternion::from_angle_y(cgmath::Deg(self.spawned.len() as f32 * 30.0)),
+            scale: cgmath::Vector3::new(0.5, 0.5, 0.5),
+            style: InstanceStyle::default()
+        };
+        self.spawned.push((self.instances.insert(instance.to_raw()), instance));
+    }
+    fn despawn_selected(&mut self) {
+        let handle = match &self.selection {
+            Some((handle, _)) => *handle,
+            None => return
+        };
+        // 场景节点的实例由 Scene 管理，这里只删除运行时生成的
+        if let Some(i) = self.spawned.iter().position(|(h, _)| *h == handle) {
+            self.instances.remove(self.spawned.swap_remove(i).0);
+            self.selection = None;
+        } else {
+            log::info!("selected instance belongs to the scene and can't be despawned");
+        }
+    }
+    fn update_light(&mut self, dt: instant::Duration){
+        let amount = cgmath::Quaternion::from_angle_y(cgmath::Deg(60.0 * dt.as_secs_f32()));
+        self.scene.update_local(self.light_pivot_node, |t| t.rotation = amount * t.rotation);
+    }
+    fn back_to_front_instances(&self) -> Vec<u32> {
+        let center = self.obj_model.bounds()
+            .map(|b| b.center())
+            .unwrap_or(cgmath::Point3::origin());
+        back_to_front(self.instances.instances(), center, self.camera.position)
     }
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
@@ -503,6 +993,13 @@ impl State {
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder")
         });
+        let shaded = !self.debug.replaces_shading();
+        let runs = model::instance_runs(self.instances.instances().iter().map(InstanceRaw::material_override));
+        let transparent_order = if shaded && self.obj_model.has_alpha_mode(AlphaMode::Blend, &runs) {
+            self.back_to_front_instances()
+        } else {
+            Vec::new()
+        };
         // 写在花括号里是为了让_render_pass在花括号执行完后销毁，
         // 否则_render_pass可能一直borrow着encoder，会造成encoder.finish销毁encoder时报错
         // 因为_render_pass可能在encoder销毁后才销毁
@@ -510,8 +1007,8 @@ impl State {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: self.msaa_view.as_ref().unwrap_or(&view),
+                    resolve_target: self.msaa_view.as_ref().map(|_| &view),
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(self.clear_color),
                         store: true
@@ -526,7 +1023,7 @@ impl State {
                     stencil_ops: None 
                 })
             });
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
 
             render_pass.set_pipeline(&self.light_render_pipeline);
             render_pass.draw_light_mesh(
@@ -534,20 +1031,48 @@ impl State {
                 &self.camera_bind_group, 
                 &self.light_bind_group
             );
-            // render_pass.draw_light_model(
-            //     &self.obj_model, 
-            //     &self.camera_bind_group, 
-            //     &self.light_bind_group
-            // );
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
-                &self.obj_model, 
-                0..self.instances.len() as u32, 
-                &self.camera_bind_group,
-                &self.light_bind_group
+            for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask].into_iter().filter(|_| shaded) {
+                render_pass.draw_model_instanced_alpha(
+                    &self.obj_model, 
+                    alpha_mode,
+                    &self.material_pipelines,
+                    &runs,
+                    &self.camera_bind_group,
+                    &self.light_bind_group
+                );
+            }
+            // 半透明的部分最后画，每个实例单独画一次才能保证从后往前
+            for &i in &transparent_order {
+                let run = InstanceRun {
+                    material: self.instances.instances()[i as usize].material_override(),
+                    instances: i..i + 1
+                };
+                render_pass.draw_model_instanced_alpha(
+                    &self.obj_model, 
+                    AlphaMode::Blend,
+                    &self.material_pipelines,
+                    &[run], 
+                    &self.camera_bind_group,
+                    &self.light_bind_group
+                );
+            }
+            self.debug.draw(
+                &mut render_pass,
+                &self.obj_model,
+                0..self.instances.len() as u32,
+                &self.camera_bind_group
             );
         }
+        self.picker.encode(
+            &self.device,
+            &mut encoder,
+            &self.obj_model,
+            self.instance_buffer.buffer(),
+            self.instances.len() as u32,
+            &self.camera_bind_group
+        );
         self.queue.submit(std::iter::once(encoder.finish()));
+        self.picker.after_submit();
         output.present();
 
         Ok(())
@@ -556,8 +1081,16 @@ impl State {
 
 
 
-#[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
-pub async fn run() {
+// Web 上的入口，错误作为异常抛给页面
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(start)]
+pub async fn start() -> Result<(), JsValue> {
+    run().await.map_err(|e| JsValue::from_str(&e.report()))
+}
+
+// 只有在创建窗口、初始化 GPU 或加载初始资源失败时才会返回；之后事件循环
+// 接管进程，直到窗口关闭
+pub async fn run() -> Result<(), Error> {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -567,7 +1100,7 @@ pub async fn run() {
         }
     }
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    let window = WindowBuilder::new().build(&event_loop)?;
 
     #[cfg(target_arch = "wasm32")]
     {
@@ -586,12 +1119,14 @@ pub async fn run() {
                 Some(())
             })
             .expect("Couldn't append canvas to document body.");
+
+        vfs::fetch_pack().await;
     }
 
-    let mut state = State::new(&window).await;
+    let mut state = State::new(&window).await?;
     let mut last_render_time = instant::Instant::now();
     event_loop.run(move |event, _, control_flow| match event {
-        Event::DeviceEvent { event: DeviceEvent::MouseMotion{ delta, }, .. } => if state.mouse_pressed {
+        Event::DeviceEvent { event: DeviceEvent::MouseMotion{ delta, }, .. } if state.mouse_pressed => {
             state.camera_controller.process_mouse(delta.0, delta.1)
         },
         Event::WindowEvent {
@@ -629,7 +1164,7 @@ pub async fn run() {
                 Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
                 Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                 // 其他报错
-                Err(e) => eprintln!("{:?}", e)
+                Err(e) => log::error!("{}", Error::from(e).report())
             }
         }
         Event::MainEventsCleared => {
@@ -639,57 +1174,139 @@ pub async fn run() {
     })
 }
 
-fn create_render_pipeline(
+// 与 render_pipeline_layout 中 bind group 的顺序一致。目前所有材质都有法线贴图
+const MATERIAL_SHADER_DEFINES: &[(&str, &str)] = &[("CAMERA_GROUP", "1"), ("LIGHT_GROUP", "2"), ("NORMAL_MAP", "")];
+// 与 light_pipeline_layout 中 bind group 的顺序一致
+const LIGHT_SHADER_DEFINES: &[(&str, &str)] = &[("CAMERA_GROUP", "0"), ("LIGHT_GROUP", "1")];
+
+fn create_light_pipeline(
     device: &wgpu::Device,
+    cache: &mut PipelineCache,
     layout: &wgpu::PipelineLayout,
+    shader: &str,
     color_format: wgpu::TextureFormat,
-    depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor
-) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor { 
-        label: Some("Render Pipeline"), 
-        layout: Some(layout), 
-        vertex: wgpu::VertexState { 
-            module: &shader, 
-            entry_point: "vs_main", 
-            buffers: vertex_layouts 
-        }, 
-        primitive: wgpu::PrimitiveState { 
-            topology: wgpu::PrimitiveTopology::TriangleList, 
-            strip_index_format: None, 
-            front_face: wgpu::FrontFace::Ccw, 
-            cull_mode: Some(wgpu::Face::Back), 
-            unclipped_depth: false, 
-            polygon_mode: wgpu::PolygonMode::Fill, 
-            conservative: false 
-        }, 
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState { 
-            format, 
-            depth_write_enabled: true, 
-            depth_compare: wgpu::CompareFunction::Less, 
-            stencil: wgpu::StencilState::default(), 
-            bias: wgpu::DepthBiasState::default() 
-        }), 
-        multisample: wgpu::MultisampleState { 
-            count: 1, 
-            mask: !0, 
-            alpha_to_coverage_enabled: false 
-        }, 
-        fragment: Some(wgpu::FragmentState {
-            module: &shader, 
-            entry_point: "fs_main", 
-            targets: &[Some(wgpu::ColorTargetState { 
-                format: color_format, 
-                blend: Some(wgpu::BlendState { 
-                    color: wgpu::BlendComponent::REPLACE, 
-                    alpha: wgpu::BlendComponent::REPLACE 
-                }), 
-                write_mask: wgpu::ColorWrites::ALL 
-            })] 
-        }),
-        multiview: None,
-    })
-}
\ No newline at end of file
+    multisample: wgpu::MultisampleState
+) -> Rc<wgpu::RenderPipeline> {
+    PipelineBuilder::new("Light Pipeline", layout, shader, color_format)
+        .vertex_layouts(&[PositionVertex::desc()])
+        .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+        .multisample(multisample)
+        .build(device, cache)
+}
+
+// 每个材质按 alpha 模式和是否双面选择管线变体，相同的变体共用同一条管线。
+// 实例可以覆盖材质，所以模型里有点云时每个材质都有画点的变体。
+// multisample.alpha_to_coverage_enabled 只用于开启了多重采样时的 Mask 材质
+fn create_material_pipelines(
+    device: &wgpu::Device,
+    cache: &mut PipelineCache,
+    layout: &wgpu::PipelineLayout,
+    shader: &str,
+    color_format: wgpu::TextureFormat,
+    multisample: wgpu::MultisampleState,
+    model: &Model
+) -> Vec<MaterialPipelines> {
+    let vs_entry = match model.vertex_layout {
+        VertexLayout::Full => "vs_main",
+        VertexLayout::Packed => "vs_main_packed"
+    };
+    let has_points = model.meshes.iter().any(|m| m.topology == wgpu::PrimitiveTopology::PointList);
+    let create = |material: &model::Material, topology: wgpu::PrimitiveTopology, cache: &mut PipelineCache| {
+        let builder = PipelineBuilder::new("Render Pipeline", layout, shader, color_format)
+            .entry_points(vs_entry, "fs_main")
+            .vertex_layouts(&[PositionVertex::desc(), InstanceRaw::desc(), model.vertex_layout.desc()])
+            .topology(topology)
+            .cull_mode(if material.double_sided { None } else { Some(wgpu::Face::Back) })
+            .multisample(wgpu::MultisampleState { alpha_to_coverage_enabled: false, ..multisample });
+        match material.alpha_mode {
+            AlphaMode::Opaque => builder
+                .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less),
+            AlphaMode::Mask => builder
+                .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+                .multisample(wgpu::MultisampleState {
+                    alpha_to_coverage_enabled: multisample.alpha_to_coverage_enabled && multisample.count > 1,
+                    ..multisample
+                }),
+            AlphaMode::Blend => builder
+                .depth(Texture::DEPTH_FORMAT, false, wgpu::CompareFunction::Less)
+                .blend(Some(wgpu::BlendState::ALPHA_BLENDING))
+        }.build(device, cache)
+    };
+    model.materials.iter().map(|material| MaterialPipelines {
+        triangles: create(material, wgpu::PrimitiveTopology::TriangleList, cache),
+        points: has_points.then(|| create(material, wgpu::PrimitiveTopology::PointList, cache))
+    }).collect()
+}
+
+fn create_msaa_view(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    sample_count: u32
+) -> Option<wgpu::TextureView> {
+    if sample_count <= 1 {
+        return None;
+    }
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("msaa_texture"),
+        size: wgpu::Extent3d { width: config.width, height: config.height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[]
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
+
+// 按模型包围盒中心在世界空间里到相机的距离排序，最远的在前。
+// 距离相同的实例保持原来的顺序，避免每帧闪烁
+fn back_to_front(instances: &[InstanceRaw], center: cgmath::Point3<f32>, eye: cgmath::Point3<f32>) -> Vec<u32> {
+    use cgmath::MetricSpace;
+    let mut order = instances.iter().enumerate()
+        .map(|(i, raw)| {
+            let position = cgmath::Transform::transform_point(&raw.model_matrix(), center);
+            (i as u32, position.distance2(eye))
+        })
+        .collect::<Vec<_>>();
+    order.sort_by(|a, b| b.1.total_cmp(&a.1));
+    order.into_iter().map(|(i, _)| i).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{instance::InstanceRaw, shaders::reflect::ShaderReflection, vertex::{PositionVertex, Vertex, VertexLayout}};
+
+    #[test]
+    fn vertex_layouts_match_wgsl() {
+        let shader = ShaderReflection::compose("shader.wgsl", super::MATERIAL_SHADER_DEFINES);
+        shader.check_vertex_input("vs_main", &[PositionVertex::desc(), InstanceRaw::desc(), VertexLayout::Full.desc()]);
+        shader.check_vertex_input("vs_main_packed", &[PositionVertex::desc(), InstanceRaw::desc(), VertexLayout::Packed.desc()]);
+        ShaderReflection::compose("light/light.wgsl", super::LIGHT_SHADER_DEFINES)
+            .check_vertex_input("vs_main", &[PositionVertex::desc()]);
+    }
+    #[test]
+    fn transparent_instances_sort_by_distance() {
+        use crate::instance::InstanceStyle;
+        let at = |x: f32, z: f32| InstanceRaw::new(
+            cgmath::Matrix4::from_translation(cgmath::Vector3::new(x, 0.0, z)),
+            &InstanceStyle::default()
+        );
+        let eye = cgmath::Point3::new(0.0, 0.0, 0.0);
+        let center = cgmath::Point3::new(0.0, 0.0, 0.0);
+        let instances = [at(0.0, -2.0), at(0.0, -10.0), at(0.0, 3.0), at(0.0, -5.0)];
+        assert_eq!(super::back_to_front(&instances, center, eye), [1, 3, 2, 0]);
+        // 偏离视线的实例按距离排，深度更浅也可能更远；距离相同时保持下标顺序
+        let instances = [at(8.0, -4.0), at(0.0, -6.0), at(-1.0, -4.0), at(6.0, 0.0), at(0.0, 6.0), at(4.0, -3.0)];
+        assert_eq!(super::back_to_front(&instances, center, eye), [0, 1, 3, 4, 5, 2]);
+        // 包围盒中心不在原点时按变换后的中心
+        let center = cgmath::Point3::new(0.0, 0.0, -20.0);
+        let instances = [at(0.0, 0.0), at(0.0, 30.0)];
+        assert_eq!(super::back_to_front(&instances, center, eye), [0, 1]);
+        // 相机不在原点
+        let eye = cgmath::Point3::new(10.0, 0.0, -20.0);
+        let instances = [at(10.0, 0.0), at(0.0, 0.0)];
+        assert_eq!(super::back_to_front(&instances, center, eye), [1, 0]);
+        assert!(super::back_to_front(&[], center, eye).is_empty());
+    }
+}
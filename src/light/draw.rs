@@ -0,0 +1,44 @@
+use std::ops::Range;
+use crate::model::Mesh;
+
+pub trait DrawLight<'a> {
+    fn draw_light_mesh(
+        &mut self,
+        mesh: &'a Mesh,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup
+    );
+    fn draw_light_mesh_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup
+    );
+}
+
+impl<'a, 'b> DrawLight<'b> for wgpu::RenderPass<'a>
+where
+ 'b: 'a {
+    fn draw_light_mesh(
+            &mut self,
+            mesh: &'a Mesh,
+            camera_bind_group: &'a wgpu::BindGroup,
+            light_bind_group: &'a wgpu::BindGroup
+        ) {
+        self.draw_light_mesh_instanced(mesh, 0..1, camera_bind_group, light_bind_group);
+    }
+    fn draw_light_mesh_instanced(
+            &mut self,
+            mesh: &'a Mesh,
+            instances: Range<u32>,
+            camera_bind_group: &'a wgpu::BindGroup,
+            light_bind_group: &'a wgpu::BindGroup
+        ) {
+        // 光源只用位置
+        self.set_vertex_buffer(0, mesh.position_buffer.slice(..));
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, light_bind_group, &[]);
+        mesh.draw_elements(self, instances);
+    }
+}
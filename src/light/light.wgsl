@@ -1,18 +1,6 @@
 // vertex shader
-struct Camera {
-    view_pos: vec4f,
-    view_proj: mat4x4f
-}
-@group(0) @binding(0)
-var<uniform> camera: Camera;
-
-struct Light {
-    position: vec3f,
-    intensity: f32,
-    color: vec3f
-}
-@group(1) @binding(0)
-var<uniform> light: Light;
+#include "shaders/camera.wgsl"
+#include "shaders/light.wgsl"
 
 struct VertexInput {
     @location(0) position: vec3f
@@ -1,4 +1,4 @@
-mod light;
-pub use light::DrawLight;
+mod draw;
+pub use draw::DrawLight;
 mod point;
 pub use point::PointLightUniform;
@@ -1,5 +1,3 @@
-use wgpu::util::DeviceExt;
-
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct PointLightUniform {
@@ -21,46 +19,14 @@ impl PointLightUniform {
     }
 }
 
-pub struct PointLight {
-    uniform: PointLightUniform,
-    buffer: Option<wgpu::Buffer>
-}
-impl PointLight {
-    pub fn new(position: [f32; 3], color: [f32; 3], intensity: f32) -> PointLight {
-        PointLight { 
-            uniform: PointLightUniform::new(position, color, intensity), 
-            buffer: None
-        }
-    }
-    pub fn init(self, device: wgpu::Device) {
-        let light_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Light VB"),
-                contents: bytemuck::cast_slice(&[self.uniform]),
-                // use copy_dst to update light position
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
-            }
-        );
-        let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: None,
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None
-                },
-                count: None
-            }]
-        });
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor { 
-            label: None, 
-            layout: &light_bind_group_layout, 
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding()
-            }] 
-        });
+#[cfg(test)]
+mod tests {
+    use super::PointLightUniform;
+    use crate::shaders::reflect::{gpu_fields, ShaderReflection};
+
+    #[test]
+    fn uniform_matches_wgsl() {
+        ShaderReflection::compose("shaders/light.wgsl", &[("LIGHT_GROUP", "0")])
+            .check_uniform::<PointLightUniform>("light", &gpu_fields!(PointLightUniform: position, intensity, color));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,264 @@
+use std::{collections::{HashMap, HashSet}, sync::mpsc::{self, Receiver, Sender}, time::SystemTime};
+
+use crate::{error::Result, model::ModelData, resources::{self, MeshProcessing}};
+
+// 热加载时检查文件修改时间的间隔
+const POLL_INTERVAL: instant::Duration = instant::Duration::from_millis(500);
+
+// 文件数和字节数按实际读到的计算，包括烘焙文件和检查烘焙文件时重新读的源文件
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub requested: u32,
+    pub loaded: u32,
+    pub failed: u32,
+    pub files: u32,
+    pub bytes: u64
+}
+
+impl LoadProgress {
+    pub fn pending(&self) -> u32 {
+        self.requested - self.loaded - self.failed
+    }
+}
+
+// 热加载时磁盘上改过的文件，和因此在后台重新加载的模型
+#[derive(Debug, Default)]
+pub struct AssetChanges {
+    pub files: Vec<String>,
+    pub models: Vec<String>
+}
+
+enum Message {
+    File { model: String, file: String, bytes: u64, modified: Option<SystemTime> },
+    Done(String, Result<ModelData>)
+}
+
+// 加载过的模型用到的文件和读取时的修改时间
+struct Watched {
+    processing: MeshProcessing,
+    files: Vec<(String, Option<SystemTime>)>
+}
+
+// 在后台读取和处理模型：原生平台每个模型一个工作线程，Web 上用 spawn_local 异步下载。
+// 上传到 GPU 需要 device，留给主线程在 poll 之后做
+pub struct AssetLoader {
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+    // 正在加载的文件，同一个文件不会同时加载两次
+    loading: HashSet<String>,
+    progress: LoadProgress,
+    watch: bool,
+    last_poll: instant::Instant,
+    watched: HashMap<String, Watched>,
+    // 正在加载的模型已经读到的文件
+    reading: HashMap<String, Vec<(String, Option<SystemTime>)>>
+}
+
+impl AssetLoader {
+    // 设置了 `ASSET_HOT_RELOAD` 时监视已加载模型的文件，见 `poll_changes`
+    pub fn new() -> Self {
+        Self::with_watch(resources::hot_reload())
+    }
+
+    fn with_watch(watch: bool) -> Self {
+        if watch {
+            log::info!("asset hot reload enabled");
+        }
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            loading: HashSet::new(),
+            progress: LoadProgress::default(),
+            watch,
+            last_poll: instant::Instant::now(),
+            watched: HashMap::new(),
+            reading: HashMap::new()
+        }
+    }
+
+    // `file_name` 已经在加载时返回 false，结果只会由 `poll` 返回一次
+    pub fn load_model(&mut self, file_name: &str, processing: MeshProcessing) -> bool {
+        if !self.loading.insert(file_name.to_owned()) {
+            return false;
+        }
+        self.progress.requested += 1;
+        if self.watch {
+            self.reading.insert(file_name.to_owned(), Vec::new());
+            self.watched.entry(file_name.to_owned())
+                .or_insert_with(|| Watched { processing, files: Vec::new() })
+                .processing = processing;
+        }
+        let sender = self.sender.clone();
+        let file_name = file_name.to_owned();
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                wasm_bindgen_futures::spawn_local(load_model(file_name, processing, sender));
+            } else {
+                std::thread::spawn(move || pollster::block_on(load_model(file_name, processing, sender)));
+            }
+        }
+        true
+    }
+
+    // 返回上次调用以来加载完成的模型，包括失败的，并更新进度
+    pub fn poll(&mut self) -> Vec<(String, Result<ModelData>)> {
+        let mut finished = Vec::new();
+        for message in self.receiver.try_iter() {
+            match message {
+                Message::File { model, file, bytes, modified } => {
+                    self.progress.files += 1;
+                    self.progress.bytes += bytes;
+                    if let Some(files) = self.reading.get_mut(&model) {
+                        files.push((file, modified));
+                    }
+                }
+                Message::Done(file_name, result) => {
+                    self.loading.remove(&file_name);
+                    let files = self.reading.remove(&file_name);
+                    if result.is_ok() {
+                        self.progress.loaded += 1;
+                        // 成功时换成这次用到的文件，新引用的贴图也会被检查
+                        if let (Some(watched), Some(files)) = (self.watched.get_mut(&file_name), files) {
+                            watched.files = files;
+                        }
+                    } else {
+                        // 失败时继续检查原来的文件，修好后会再次加载
+                        self.progress.failed += 1;
+                    }
+                    finished.push((file_name, result));
+                }
+            }
+        }
+        finished
+    }
+
+    // 开启热加载时检查每个已加载模型的文件，重新加载文件有改动的模型。
+    // 结果和其他加载一样由 `poll` 返回
+    pub fn poll_changes(&mut self) -> AssetChanges {
+        let mut changes = AssetChanges::default();
+        if !self.watch || self.last_poll.elapsed() < POLL_INTERVAL {
+            return changes;
+        }
+        self.last_poll = instant::Instant::now();
+        let mut reload = Vec::new();
+        for (model, watched) in &mut self.watched {
+            // 正在加载的可能读到的是旧文件，等加载完再检查
+            if self.loading.contains(model) {
+                continue;
+            }
+            let mut changed = false;
+            for (file, modified) in &mut watched.files {
+                let current = resources::modified(file);
+                if current != *modified {
+                    // 先记下新的时间，加载失败时不会反复重试
+                    *modified = current;
+                    changed = true;
+                    if !changes.files.contains(file) {
+                        changes.files.push(file.clone());
+                    }
+                }
+            }
+            if changed {
+                reload.push((model.clone(), watched.processing));
+            }
+        }
+        reload.sort_by(|a, b| a.0.cmp(&b.0));
+        for (model, processing) in reload {
+            self.load_model(&model, processing);
+            changes.models.push(model);
+        }
+        changes
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+}
+
+async fn load_model(file_name: String, processing: MeshProcessing, sender: Sender<Message>) {
+    let result = resources::read_model(&file_name, &processing, &mut |file, data| {
+        // 接收端已经释放（程序退出）时忽略
+        let _ = sender.send(Message::File {
+            model: file_name.clone(),
+            file: file.to_owned(),
+            bytes: data.len() as u64,
+            modified: resources::modified(file)
+        });
+    }).await;
+    let _ = sender.send(Message::Done(file_name, result));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AssetLoader, LoadProgress};
+    use crate::resources::MeshProcessing;
+
+    #[test]
+    fn loads_in_the_background_and_reports_failures() {
+        let mut loader = AssetLoader::new();
+        assert!(loader.load_model("cube.obj", MeshProcessing::default()));
+        assert!(loader.load_model("missing.obj", MeshProcessing::default()));
+        assert!(!loader.load_model("cube.obj", MeshProcessing::default()));
+        assert_eq!(loader.progress().pending(), 2);
+
+        let mut finished = Vec::new();
+        let start = std::time::Instant::now();
+        while finished.len() < 2 {
+            assert!(start.elapsed().as_secs() < 60, "loading timed out");
+            finished.extend(loader.poll());
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        finished.sort_by(|a, b| a.0.cmp(&b.0));
+        assert!(finished[0].1.as_ref().is_ok_and(|m| !m.meshes.is_empty()));
+        assert!(matches!(finished[1].1, Err(crate::Error::NotFound { .. })));
+
+        let LoadProgress { requested, loaded, failed, files, bytes } = loader.progress();
+        assert_eq!((requested, loaded, failed), (2, 1, 1));
+        // 至少有 OBJ、MTL 和两张贴图，或者烘焙文件
+        assert!(files >= 1 && bytes > 0, "{} files, {} bytes", files, bytes);
+    }
+
+    fn wait(loader: &mut AssetLoader) -> (String, crate::error::Result<crate::model::ModelData>) {
+        let start = std::time::Instant::now();
+        loop {
+            assert!(start.elapsed().as_secs() < 60, "loading timed out");
+            if let Some(finished) = loader.poll().pop() {
+                return finished;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn reloads_changed_files() {
+        let path = std::path::Path::new(env!("OUT_DIR")).join("res").join("hot_reload_test.obj");
+        std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n").unwrap();
+        let mut loader = AssetLoader::with_watch(true);
+        loader.load_model("hot_reload_test.obj", MeshProcessing::default());
+        assert!(wait(&mut loader).1.is_ok());
+
+        std::thread::sleep(super::POLL_INTERVAL);
+        assert!(loader.poll_changes().models.is_empty());
+
+        std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nf 1 3 4\n").unwrap();
+        // 文件系统的时间精度可能很粗，直接改修改时间
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        std::thread::sleep(super::POLL_INTERVAL);
+        let changes = loader.poll_changes();
+        assert_eq!(changes.files, ["hot_reload_test.obj"]);
+        assert_eq!(changes.models, ["hot_reload_test.obj"]);
+        let (file_name, result) = wait(&mut loader);
+        assert_eq!(file_name, "hot_reload_test.obj");
+        assert_eq!(result.unwrap().meshes[0].indices.len(), 6);
+
+        // 删除后加载失败，只报告一次
+        std::fs::remove_file(&path).unwrap();
+        std::thread::sleep(super::POLL_INTERVAL);
+        assert_eq!(loader.poll_changes().models.len(), 1);
+        assert!(matches!(wait(&mut loader).1, Err(crate::Error::NotFound { .. })));
+        std::thread::sleep(super::POLL_INTERVAL);
+        assert!(loader.poll_changes().models.is_empty());
+    }
+}
@@ -1,6 +1,8 @@
 use learn_wgpu::run;
-use pollster;
 
 fn main() {
-    pollster::block_on(run());
+    if let Err(e) = pollster::block_on(run()) {
+        eprintln!("error: {}", e.report());
+        std::process::exit(1);
+    }
 }
@@ -1,81 +1,163 @@
-use std::ops::Range;
+use std::{ops::Range, rc::Rc};
 
-use crate::{vertex::Vertex, texture};
+use cgmath::Point3;
+use wgpu::util::DeviceExt;
 
+use crate::{assets::{Assets, Handle}, error::{Error, Result}, vertex::{self, VertexLayout}, texture, ray::{self, Aabb, Ray}};
+
+// CPU 端的顶点。上传时按 vertex::VertexLayout 拆成位置和属性两个流
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
-    pub tangent: [f32; 3],
-    pub bitangent: [f32; 3],
+    // xyz 是切线，w 是副切线的方向，见 geometry::generate_tangents
+    pub tangent: [f32; 4],
+    // 线性 RGBA，乘到漫反射颜色上，没有顶点颜色的模型是白色
+    pub color: [f32; 4],
 }
 impl Default for ModelVertex {
     fn default() -> Self {
-        ModelVertex { position: [0.0; 3], tex_coords: [0.0; 2], normal: [1.0; 3], tangent: [1.0; 3], bitangent: [1.0; 3] }
-    }
-}
-impl Vertex for ModelVertex{
-    // const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0=>Float32x3, 1=>Float32x2];
-    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x3
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 3,
-                    format: wgpu::VertexFormat::Float32x2
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
-                    shader_location: 4,
-                    format: wgpu::VertexFormat::Float32x3
-                }
-            ],
-            // attributes: &Self::ATTRIBS
-        }
+        ModelVertex { position: [0.0; 3], tex_coords: [0.0; 2], normal: [1.0; 3], tangent: [1.0, 0.0, 0.0, 1.0], color: [1.0; 4] }
     }
 }
 
 pub struct Mesh {
     pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    // vertex::PositionVertex
+    pub position_buffer: wgpu::Buffer,
+    // 格式见 Model::vertex_layout，只用位置的网格（光源立方体）没有
+    pub attribute_buffer: Option<wgpu::Buffer>,
+    // 点云没有索引
+    pub index_buffer: Option<wgpu::Buffer>,
+    // 顶点数不超过 65536 时用 Uint16
+    pub index_format: wgpu::IndexFormat,
+    // 索引数，点云是顶点数
     pub num_elements: u32,
-    pub material: usize
+    pub topology: wgpu::PrimitiveTopology,
+    pub material: usize,
+    // CPU 端保留的几何数据，用于射线检测和调试显示
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub bounds: Aabb
 }
+
+impl Mesh {
+    // `layout` 为 None 时只上传位置
+    pub fn from_data(device: &wgpu::Device, data: MeshData, layout: Option<VertexLayout>) -> Self {
+        let position_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Position Buffer", data.name)),
+            contents: bytemuck::cast_slice(&vertex::positions(&data.vertices)),
+            usage: wgpu::BufferUsages::VERTEX
+        });
+        let attribute_buffer = layout.map(|layout| device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Attribute Buffer", data.name)),
+            contents: &layout.encode(&data.vertices),
+            usage: wgpu::BufferUsages::VERTEX
+        }));
+        let topology = data.topology();
+        let index_buffer = (topology == wgpu::PrimitiveTopology::TriangleList).then(|| device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", data.name)),
+            contents: &data.index_bytes(),
+            usage: wgpu::BufferUsages::INDEX
+        }));
+        Self {
+            name: data.name,
+            position_buffer,
+            attribute_buffer,
+            index_buffer,
+            index_format: data.index_format,
+            num_elements: if topology == wgpu::PrimitiveTopology::PointList { data.vertices.len() } else { data.indices.len() } as u32,
+            topology,
+            material: data.material,
+            bounds: Self::compute_bounds(&data.vertices),
+            vertices: data.vertices,
+            indices: data.indices
+        }
+    }
+
+    // 画出所有图元，顶点缓冲、bind group 和管线需要已经设置好
+    pub fn draw_elements<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instances: Range<u32>) {
+        match &self.index_buffer {
+            Some(index_buffer) => {
+                render_pass.set_index_buffer(index_buffer.slice(..), self.index_format);
+                render_pass.draw_indexed(0..self.num_elements, 0, instances);
+            }
+            None => render_pass.draw(0..self.num_elements, instances)
+        }
+    }
+
+    pub fn compute_bounds(vertices: &[ModelVertex]) -> Aabb {
+        Aabb::from_points(vertices.iter().map(|v| Point3::from(v.position)))
+            .unwrap_or(Aabb { min: Point3::new(0.0, 0.0, 0.0), max: Point3::new(0.0, 0.0, 0.0) })
+    }
+
+    // `ray` 在网格（模型）空间里，返回到最近的三角形的距离，点云不会被击中
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<f32> {
+        ray::intersect_aabb(ray, &self.bounds)?;
+        self.indices.chunks_exact(3).filter_map(|c| {
+            let a = Point3::from(self.vertices[c[0] as usize].position);
+            let b = Point3::from(self.vertices[c[1] as usize].position);
+            let c = Point3::from(self.vertices[c[2] as usize].position);
+            ray::intersect_triangle(ray, a, b, c).map(|(distance, _, _)| distance)
+        }).min_by(|a, b| a.total_cmp(b))
+    }
+}
+// 决定材质用哪条管线绘制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    // 忽略 alpha
+    Opaque,
+    // alpha 低于 MaterialUniform.alpha_cutoff 的片元被丢弃，仍然写深度
+    Mask,
+    // alpha 混合，不写深度，需要从后往前画
+    Blend
+}
+
 pub struct Material {
-    pub name: String,
-    pub diffuse_texture: texture::Texture,
-    pub normal_texture: texture::Texture,
+    // bind group 用到的漫反射和法线贴图。只是持有句柄，Assets 据此知道贴图还在使用
+    _textures: [Handle<texture::Texture>; 2],
+    pub alpha_mode: AlphaMode,
+    // 不剔除背面
+    pub double_sided: bool,
     pub bind_group: wgpu::BindGroup
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    // rgb 乘到漫反射贴图上，a 是不透明度
+    pub diffuse: [f32; 4],
+    // 自发光颜色
+    pub emissive: [f32; 3],
+    // 只对 AlphaMode::Mask 有意义，其他模式为 0
+    pub alpha_cutoff: f32
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self { diffuse: [1.0; 4], emissive: [0.0; 3], alpha_cutoff: 0.0 }
+    }
+}
+
 impl Material {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: texture::Texture,
-        normal_texture: texture::Texture,
+        diffuse_texture: Handle<texture::Texture>,
+        normal_texture: Handle<texture::Texture>,
+        uniform: MaterialUniform,
+        alpha_mode: AlphaMode,
+        double_sided: bool,
         layout: &wgpu::BindGroupLayout
     ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM
+        });
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor { 
             label: Some(name), 
             layout, 
@@ -96,30 +178,200 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
             ]
         });
 
         Self {
-            name: String::from(name),
-            diffuse_texture,
-            normal_texture,
+            _textures: [diffuse_texture, normal_texture],
+            alpha_mode,
+            double_sided,
             bind_group,
         }
     }
 }
 pub struct Model {
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>
+    pub materials: Vec<Material>,
+    pub vertex_layout: VertexLayout
+}
+
+// 模型在 CPU 端处理好的数据，上传到 GPU 前的形式。
+// 从源文件加载或从烘焙文件读出，见 resources::load_model_data 和 bake
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    // 材质按下标引用，共用的贴图只存一份
+    pub textures: Vec<texture::TextureData>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<ModelVertex>,
+    // 三角形列表，为空时是点云，每个顶点画一个点
+    pub indices: Vec<u32>,
+    // 上传时索引的格式，Uint16 时所有索引都小于 65536
+    pub index_format: wgpu::IndexFormat,
+    pub material: usize
+}
+
+impl MeshData {
+    pub fn topology(&self) -> wgpu::PrimitiveTopology {
+        if self.indices.is_empty() {
+            wgpu::PrimitiveTopology::PointList
+        } else {
+            wgpu::PrimitiveTopology::TriangleList
+        }
+    }
+
+    // 按 index_format 排列的索引数据
+    pub fn index_bytes(&self) -> Vec<u8> {
+        match self.index_format {
+            wgpu::IndexFormat::Uint16 => {
+                let indices = self.indices.iter().map(|&i| i as u16).collect::<Vec<_>>();
+                bytemuck::cast_slice(&indices).to_vec()
+            }
+            wgpu::IndexFormat::Uint32 => bytemuck::cast_slice(&self.indices).to_vec()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialData {
+    pub name: String,
+    // ModelData::textures 的下标
+    pub diffuse_texture: usize,
+    pub normal_texture: usize,
+    pub uniform: MaterialUniform,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool
+}
+
+impl ModelData {
+    pub fn upload(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        layout: &wgpu::BindGroupLayout,
+        vertex_layout: VertexLayout,
+        assets: &mut Assets
+    ) -> Result<Model> {
+        self.check_limits(label, vertex_layout, &device.limits())?;
+        let textures = self.textures.iter()
+            .map(|t| assets.texture(device, queue, t))
+            .collect::<Vec<_>>();
+        let materials = self.materials.into_iter().map(|m| Material::new(
+            device,
+            &m.name,
+            textures[m.diffuse_texture].clone(),
+            textures[m.normal_texture].clone(),
+            m.uniform,
+            m.alpha_mode,
+            m.double_sided,
+            layout
+        )).collect();
+        let meshes = self.meshes.into_iter().map(|m| Mesh::from_data(device, m, Some(vertex_layout))).collect();
+        Ok(Model { meshes, materials, vertex_layout })
+    }
+
+    // 超过限制时 wgpu 会在创建时报验证错误，先检查才能作为加载失败处理
+    pub fn check_limits(&self, label: &str, vertex_layout: VertexLayout, limits: &wgpu::Limits) -> Result<()> {
+        let error = |what: String, value: u64, limit: u64| Error::GpuLimit { file: label.to_owned(), what, value, limit };
+        let max_size = limits.max_texture_dimension_2d;
+        for t in &self.textures {
+            if t.width.max(t.height) > max_size {
+                return Err(error(format!("texture {} size", t.label), t.width.max(t.height) as u64, max_size as u64));
+            }
+        }
+        for m in &self.meshes {
+            // 属性流比位置流大
+            let size = (m.vertices.len() * vertex_layout.stride().max(std::mem::size_of::<vertex::PositionVertex>())) as u64;
+            if size > limits.max_buffer_size {
+                return Err(error(format!("mesh {} vertex buffer size", m.name), size, limits.max_buffer_size));
+            }
+            let index_size = if m.index_format == wgpu::IndexFormat::Uint16 { 2 } else { 4 };
+            let size = (m.indices.len() * index_size) as u64;
+            if size > limits.max_buffer_size {
+                return Err(error(format!("mesh {} index buffer size", m.name), size, limits.max_buffer_size));
+            }
+        }
+        Ok(())
+    }
+}
+
+// 一个材质的管线。模型里有点云时还有画点的变体，见 lib.rs 的 create_material_pipelines
+pub struct MaterialPipelines {
+    pub triangles: Rc<wgpu::RenderPipeline>,
+    pub points: Option<Rc<wgpu::RenderPipeline>>
+}
+
+impl MaterialPipelines {
+    pub fn get(&self, topology: wgpu::PrimitiveTopology) -> Option<&Rc<wgpu::RenderPipeline>> {
+        match topology {
+            wgpu::PrimitiveTopology::PointList => self.points.as_ref(),
+            _ => Some(&self.triangles)
+        }
+    }
+}
+
+// 一段连续的、材质覆盖相同的实例，一次 draw 画完
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceRun {
+    pub material: Option<usize>,
+    pub instances: Range<u32>
+}
+
+// 按实例顺序，把材质覆盖相同的相邻实例分成一段
+pub fn instance_runs<I: IntoIterator<Item = Option<usize>>>(overrides: I) -> Vec<InstanceRun> {
+    let mut runs: Vec<InstanceRun> = Vec::new();
+    for (i, material) in overrides.into_iter().enumerate() {
+        let i = i as u32;
+        match runs.last_mut() {
+            Some(run) if run.material == material => run.instances.end = i + 1,
+            _ => runs.push(InstanceRun { material, instances: i..i + 1 })
+        }
+    }
+    runs
+}
+
+// 覆盖的材质不存在时用网格自己的材质
+fn select_material(mesh_material: usize, material_override: Option<usize>, material_count: usize) -> usize {
+    material_override.filter(|&m| m < material_count).unwrap_or(mesh_material)
+}
+
+impl Model {
+    // 实例用 `material_override` 覆盖材质时，画 `mesh` 所用材质在 `materials` 中的下标
+    pub fn material_index(&self, mesh: &Mesh, material_override: Option<usize>) -> usize {
+        select_material(mesh.material, material_override, self.materials.len())
+    }
+
+    pub fn has_alpha_mode(&self, alpha_mode: AlphaMode, runs: &[InstanceRun]) -> bool {
+        self.meshes.iter().any(|mesh| runs.iter().any(|run| {
+            self.materials[self.material_index(mesh, run.material)].alpha_mode == alpha_mode
+        }))
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        Aabb::from_points(self.meshes.iter().flat_map(|m| [m.bounds.min, m.bounds.max]))
+    }
+
+    // 返回 `ray`（模型空间）击中的最近的网格的下标和距离
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<(usize, f32)> {
+        let bounds = self.bounds()?;
+        ray::intersect_sphere(ray, bounds.center(), bounds.radius())?;
+        self.meshes.iter().enumerate()
+            .filter_map(|(i, mesh)| mesh.intersect_ray(ray).map(|hit| (i, hit)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
 }
 
 pub trait DrawModel<'a> {
-    fn draw_mesh(
-        &mut self, 
-        mesh: &'a Mesh, 
-        material: &'a Material, 
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup
-    );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
@@ -128,16 +380,14 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup
     );
-    fn draw_model(
-        &mut self, 
-        model: &'a Model, 
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup
-    );
-    fn draw_model_instanced(
+    // 只画材质是 `alpha_mode` 的网格，每个网格用它的材质的管线。
+    // 覆盖了材质的实例用覆盖材质的 bind group 和管线画
+    fn draw_model_instanced_alpha(
         &mut self,
         model: &'a Model,
-        instances: Range<u32>,
+        alpha_mode: AlphaMode,
+        pipelines: &'a [MaterialPipelines],
+        runs: &[InstanceRun],
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup
     );
@@ -145,16 +395,6 @@ pub trait DrawModel<'a> {
 
 impl<'a, 'b>  DrawModel<'b> for wgpu::RenderPass<'a>
 where 'b: 'a {
-    fn draw_mesh(
-        &mut self, 
-        mesh: &'b Mesh, 
-        material: &'b Material, 
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup
-    ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
-    }
-
     fn draw_mesh_instanced(
             &mut self,
             mesh: &'b Mesh,
@@ -163,31 +403,80 @@ where 'b: 'a {
             camera_bind_group: &'a wgpu::BindGroup,
             light_bind_group: &'b wgpu::BindGroup
         ) {
-        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        // 槽 1 是实例缓冲，由调用的地方设置
+        self.set_vertex_buffer(0, mesh.position_buffer.slice(..));
+        if let Some(attributes) = &mesh.attribute_buffer {
+            self.set_vertex_buffer(2, attributes.slice(..));
+        }
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
         self.set_bind_group(2, light_bind_group, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, instances)
-    }
-    fn draw_model(
-        &mut self, 
-        model: &'b Model, 
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup
-    ) {
-        self.draw_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
+        mesh.draw_elements(self, instances)
     }
-    fn draw_model_instanced(
+    fn draw_model_instanced_alpha(
             &mut self,
             model: &'b Model,
-            instances: Range<u32>,
+            alpha_mode: AlphaMode,
+            pipelines: &'b [MaterialPipelines],
+            runs: &[InstanceRun],
             camera_bind_group: &'b wgpu::BindGroup,
             light_bind_group: &'b wgpu::BindGroup
         ) {
         for mesh in &model.meshes {
-            let material = &model.materials[mesh.material];
-            self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group, light_bind_group);
+            for run in runs {
+                let index = model.material_index(mesh, run.material);
+                let material = &model.materials[index];
+                let pipeline = pipelines[index].get(mesh.topology);
+                if let (true, Some(pipeline)) = (material.alpha_mode == alpha_mode, pipeline) {
+                    self.set_pipeline(pipeline);
+                    self.draw_mesh_instanced(mesh, material, run.instances.clone(), camera_bind_group, light_bind_group);
+                }
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{instance_runs, select_material, InstanceRun, MaterialUniform};
+    use crate::{error::Error, shaders::reflect::{gpu_fields, ShaderReflection}, texture::TextureData, vertex::VertexLayout};
+
+    #[test]
+    fn textures_larger_than_the_gpu_allows_are_rejected() {
+        let mut model = crate::resources::placeholder_model();
+        let limits = wgpu::Limits::downlevel_webgl2_defaults();
+        assert!(model.check_limits("placeholder", VertexLayout::Full, &limits).is_ok());
+        model.textures.push(TextureData { label: "huge.png".to_owned(), width: 4096, height: 16, is_normal_map: false, mips: Vec::new() });
+        let error = model.check_limits("placeholder", VertexLayout::Full, &limits).unwrap_err();
+        assert!(matches!(error, Error::GpuLimit { value: 4096, limit: 2048, .. }), "{}", error);
+    }
+
+    #[test]
+    fn material_uniform_matches_wgsl() {
+        let shader = ShaderReflection::compose("shader.wgsl", crate::MATERIAL_SHADER_DEFINES);
+        shader.check_uniform::<MaterialUniform>("material", &gpu_fields!(MaterialUniform: diffuse, emissive, alpha_cutoff));
+    }
+
+    #[test]
+    fn overrides_pick_the_whole_material() {
+        // 网格用材质 1，模型有 3 个材质
+        assert_eq!(select_material(1, None, 3), 1);
+        assert_eq!(select_material(1, Some(0), 3), 0);
+        assert_eq!(select_material(1, Some(2), 3), 2);
+        // 不存在的材质
+        assert_eq!(select_material(1, Some(3), 3), 1);
+        assert_eq!(select_material(1, Some(usize::MAX), 3), 1);
+    }
+
+    #[test]
+    fn instances_are_split_by_override() {
+        assert_eq!(instance_runs([]), []);
+        assert_eq!(instance_runs([None, None, None]), [InstanceRun { material: None, instances: 0..3 }]);
+        assert_eq!(instance_runs([None, Some(2), Some(2), None, Some(0)]), [
+            InstanceRun { material: None, instances: 0..1 },
+            InstanceRun { material: Some(2), instances: 1..3 },
+            InstanceRun { material: None, instances: 3..4 },
+            InstanceRun { material: Some(0), instances: 4..5 }
+        ]);
+    }
+}
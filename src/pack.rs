@@ -0,0 +1,194 @@
+// 资源包：把整个 res 目录存成一个文件，原生平台上映射到内存，Web 上只需要下载一次。
+// 用 `cargo run --bin pack` 生成，运行时作为 vfs 的一个资源根。
+//
+// 格式（小端）：
+//   magic "LWGPUPAK", VERSION: u32, 文件数: u32
+//   每个文件是 名字长度: u32, 名字 (UTF-8，用 / 分隔), 偏移: u64, 大小: u64
+//   之后是各个文件的数据，偏移从文件开头算
+// 数据不压缩，贴图本身已经是 PNG/JPEG。
+use std::{collections::HashMap, ops::Range};
+
+use anyhow::{bail, Context};
+
+use crate::error::{Error, Result};
+
+const MAGIC: &[u8; 8] = b"LWGPUPAK";
+const VERSION: u32 = 1;
+
+// 整个包的数据，原生平台上是内存映射
+enum Storage {
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>)
+}
+
+impl std::ops::Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Storage::Mapped(map) => map,
+            Storage::Owned(data) => data
+        }
+    }
+}
+
+pub struct Pack {
+    name: String,
+    storage: Storage,
+    entries: HashMap<String, Range<usize>>
+}
+
+impl Pack {
+    pub fn from_bytes(name: &str, data: Vec<u8>) -> Result<Self> {
+        Self::new(name, Storage::Owned(data))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(path: &std::path::Path) -> Result<Self> {
+        let name = path.display().to_string();
+        let error = |source| Error::Io { file: name.clone(), root: "the file system".to_owned(), source };
+        let file = std::fs::File::open(path).map_err(error)?;
+        // 映射期间包文件被改写的话读到的数据会变，运行时不应该重新生成正在用的包
+        let map = unsafe { memmap2::Mmap::map(&file).map_err(error)? };
+        Self::new(&name, Storage::Mapped(map))
+    }
+
+    // 下载整个包。服务器上没有时返回 Ok(None)
+    #[cfg(target_arch = "wasm32")]
+    pub async fn fetch(url: reqwest::Url) -> Result<Option<Self>> {
+        let error = |source| Error::Http { file: url.to_string(), root: "the server".to_owned(), source };
+        let response = reqwest::get(url.clone()).await.map_err(error)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let data = response.error_for_status().map_err(error)?.bytes().await.map_err(error)?.to_vec();
+        Ok(Some(Self::from_bytes(url.as_str(), data)?))
+    }
+
+    fn new(name: &str, storage: Storage) -> Result<Self> {
+        let entries = read_index(&storage).map_err(|e| Error::InvalidPack { file: name.to_owned(), reason: format!("{:#}", e) })?;
+        Ok(Self { name: name.to_owned(), storage, entries })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, file_name: &str) -> Option<&[u8]> {
+        self.entries.get(file_name).map(|range| &self.storage[range.clone()])
+    }
+}
+
+fn read_index(data: &[u8]) -> anyhow::Result<HashMap<String, Range<usize>>> {
+    let mut reader = Reader(data);
+    if reader.take(MAGIC.len())? != MAGIC {
+        bail!("not a resource pack");
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        bail!("version {} (expected {})", version, VERSION);
+    }
+    let count = reader.u32()?;
+    let mut entries = HashMap::new();
+    for _ in 0..count {
+        let len = reader.u32()? as usize;
+        let name = std::str::from_utf8(reader.take(len)?).context("invalid name")?.to_owned();
+        let (offset, size) = (reader.u64()?, reader.u64()?);
+        let end = offset.checked_add(size).filter(|&end| end <= data.len() as u64);
+        let Some(end) = end else {
+            bail!("{} is outside the pack", name);
+        };
+        entries.insert(name, offset as usize..end as usize);
+    }
+    Ok(entries)
+}
+
+// 按给出的顺序写入 (名字, 数据)
+pub fn write(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let index_len = files.iter().map(|(name, _)| 4 + name.len() + 16).sum::<usize>();
+    let mut offset = (MAGIC.len() + 8 + index_len) as u64;
+    let mut out = Vec::with_capacity(offset as usize + files.iter().map(|(_, data)| data.len()).sum::<usize>());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    for (name, data) in files {
+        out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        offset += data.len() as u64;
+    }
+    for (_, data) in files {
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("unexpected end of file");
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write, Pack};
+
+    fn files() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("cube.obj".to_owned(), b"v 0 0 0".to_vec()),
+            ("textures/empty.png".to_owned(), Vec::new()),
+            ("cube.mtl".to_owned(), b"newmtl a".to_vec())
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let pack = Pack::from_bytes("test", write(&files())).unwrap();
+        assert_eq!(pack.file_count(), 3);
+        for (name, data) in files() {
+            assert_eq!(pack.get(&name), Some(&data[..]));
+        }
+        assert!(pack.get("missing.obj").is_none());
+    }
+
+    #[test]
+    fn maps_pack_files() {
+        let path = std::path::Path::new(env!("OUT_DIR")).join("pack_test.pack");
+        std::fs::write(&path, write(&files())).unwrap();
+        let pack = Pack::open(&path).unwrap();
+        assert_eq!(pack.get("cube.mtl"), Some(&b"newmtl a"[..]));
+    }
+
+    #[test]
+    fn rejects_broken_packs() {
+        let data = write(&files());
+        assert!(Pack::from_bytes("test", data[..data.len() - 1].to_vec()).is_err());
+        assert!(Pack::from_bytes("test", data[..20].to_vec()).is_err());
+        let mut other = data.clone();
+        other[8] = 9;
+        assert!(Pack::from_bytes("test", other).is_err());
+        assert!(Pack::from_bytes("test", b"LWGPUBAK".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,22 @@
+use cgmath::SquareMatrix;
+
+use crate::{model::Model, ray::Ray};
+use super::PickResult;
+
+// CPU 端的拾取：把世界空间的射线变换到每个实例的局部空间，再与网格求交
+pub fn pick_instances<I>(ray: &Ray, model: &Model, instances: I) -> Option<PickResult>
+where
+    I: IntoIterator<Item = cgmath::Matrix4<f32>>
+{
+    instances.into_iter().enumerate().filter_map(|(i, model_matrix)| {
+        let inv_model = model_matrix.invert()?;
+        let (mesh, distance) = model.intersect_ray(&ray.transform(&inv_model))?;
+        Some((i, mesh, distance))
+    })
+    .min_by(|a, b| a.2.total_cmp(&b.2))
+    .map(|(instance, mesh, distance)| PickResult {
+        instance,
+        mesh: model.meshes[mesh].name.clone(),
+        position: ray.at(distance)
+    })
+}
@@ -0,0 +1,4 @@
+mod picker;
+pub use picker::{Picker, PickResult};
+mod cpu;
+pub use cpu::pick_instances;
@@ -0,0 +1,400 @@
+use std::{rc::Rc, sync::{Arc, Mutex}, task::Poll};
+
+use cgmath::{SquareMatrix, Matrix4, Point3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    instance::InstanceRaw,
+    model::Model,
+    pipeline::{PipelineBuilder, PipelineCache},
+    texture::Texture,
+    vertex::{PositionVertex, Vertex}
+};
+
+pub const PICK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Uint;
+// PICK_FORMAT 的一个纹素
+const PICK_TEXEL_SIZE: wgpu::BufferAddress = 16;
+
+#[derive(Debug, Clone)]
+pub struct PickResult {
+    pub instance: usize,
+    pub mesh: String,
+    pub position: Point3<f32>
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PickMeshUniform {
+    index: u32,
+    // 补齐到 16 字节对齐
+    _padding: [u32; 3]
+}
+
+// 正在读回的像素，以及渲染它时的目标大小和视图投影矩阵的逆
+#[derive(Clone, Copy)]
+struct PickRequest {
+    x: u32,
+    y: u32,
+    size: (u32, u32),
+    inv_view_proj: Matrix4<f32>
+}
+
+// picking.wgsl 写入的纹素：实例下标 + 1、网格下标、深度的位。
+// 返回 (实例, 网格, 深度)，没有画到的地方返回 None
+fn decode_texel([instance, mesh, depth, _]: [u32; 4]) -> Option<(usize, usize, f32)> {
+    if instance == 0 {
+        return None;
+    }
+    Some((instance as usize - 1, mesh as usize, f32::from_bits(depth)))
+}
+
+// 像素中心 -> NDC
+fn pixel_to_ndc(x: u32, y: u32, (width, height): (u32, u32)) -> (f32, f32) {
+    let ndc_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+    let ndc_y = 1.0 - (y as f32 + 0.5) / height as f32 * 2.0;
+    (ndc_x, ndc_y)
+}
+
+enum PickStage {
+    Idle,
+    Requested(PickRequest),
+    Encoded(PickRequest),
+    Mapping(PickRequest, Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>)
+}
+
+pub struct Picker {
+    layout: wgpu::PipelineLayout,
+    pipeline: Rc<wgpu::RenderPipeline>,
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    // 主渲染的深度缓冲可能是多重采样的，拾取用自己的
+    depth_texture: Texture,
+    size: (u32, u32),
+    mesh_layout: wgpu::BindGroupLayout,
+    mesh_bind_group: wgpu::BindGroup,
+    mesh_capacity: usize,
+    mesh_stride: wgpu::BufferAddress,
+    readback_buffer: wgpu::Buffer,
+    stage: PickStage
+}
+
+impl Picker {
+    // `shader` 是预处理后的 picking.wgsl
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &str,
+        pipelines: &mut PipelineCache
+    ) -> Self {
+        let mesh_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("pick_mesh_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PickMeshUniform>() as u64)
+                },
+                count: None
+            }]
+        });
+        let mesh_stride = (std::mem::size_of::<PickMeshUniform>() as wgpu::BufferAddress)
+            .max(device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress);
+        let mesh_bind_group = Self::create_mesh_bind_group(device, &mesh_layout, mesh_stride, 1);
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pick Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &mesh_layout],
+            push_constant_ranges: &[]
+        });
+        let pipeline = Self::create_pipeline(device, pipelines, &layout, shader);
+
+        let (id_texture, id_view) = Self::create_id_texture(device, config.width, config.height);
+        let depth_texture = Texture::create_depth_texture(device, config, 1, "pick_depth_texture");
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Readback Buffer"),
+            size: PICK_TEXEL_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        });
+
+        Self {
+            layout,
+            pipeline,
+            id_texture,
+            id_view,
+            depth_texture,
+            size: (config.width, config.height),
+            mesh_layout,
+            mesh_bind_group,
+            mesh_capacity: 1,
+            mesh_stride,
+            readback_buffer,
+            stage: PickStage::Idle
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        layout: &wgpu::PipelineLayout,
+        shader: &str
+    ) -> Rc<wgpu::RenderPipeline> {
+        PipelineBuilder::new("Pick Pipeline", layout, shader, PICK_FORMAT)
+            .vertex_layouts(&[PositionVertex::desc(), InstanceRaw::desc()])
+            .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+            // 整数格式的目标不能混合
+            .blend(None)
+            .build(device, pipelines)
+    }
+
+    // 用新的源码创建拾取管线，但还不使用，见 `set_pipeline`
+    pub fn build_pipeline(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shader: &str
+    ) -> Rc<wgpu::RenderPipeline> {
+        Self::create_pipeline(device, pipelines, &self.layout, shader)
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: Rc<wgpu::RenderPipeline>) {
+        self.pipeline = pipeline;
+    }
+
+    fn create_id_texture(device: &wgpu::Device, width: u32, height: u32) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("pick_id_texture"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PICK_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[]
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    fn create_mesh_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        stride: wgpu::BufferAddress,
+        capacity: usize
+    ) -> wgpu::BindGroup {
+        // 每个网格下标放在自己的动态偏移处
+        let mut contents = vec![0u8; stride as usize * capacity];
+        for i in 0..capacity {
+            let uniform = PickMeshUniform { index: i as u32, _padding: [0; 3] };
+            let start = i * stride as usize;
+            contents[start..start + std::mem::size_of::<PickMeshUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Pick Mesh Buffer"),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pick_mesh_bind_group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<PickMeshUniform>() as u64)
+                })
+            }]
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        let (id_texture, id_view) = Self::create_id_texture(device, config.width, config.height);
+        self.id_texture = id_texture;
+        self.id_view = id_view;
+        self.depth_texture = Texture::create_depth_texture(device, config, 1, "pick_depth_texture");
+        self.size = (config.width, config.height);
+        // 还没渲染的拾取请求可能已经在新纹理的范围之外
+        if matches!(self.stage, PickStage::Requested(_)) {
+            self.stage = PickStage::Idle;
+        }
+    }
+
+    // 请求在下一帧拾取 (x, y) 处的像素。
+    // 上一次拾取还在读回时忽略
+    pub fn request(&mut self, x: u32, y: u32, view_proj: Matrix4<f32>) {
+        if !matches!(self.stage, PickStage::Idle | PickStage::Requested(_)) {
+            return;
+        }
+        if x >= self.size.0 || y >= self.size.1 {
+            return;
+        }
+        if let Some(inv_view_proj) = view_proj.invert() {
+            self.stage = PickStage::Requested(PickRequest { x, y, size: self.size, inv_view_proj });
+        }
+    }
+
+    // 画出模型的实例和网格 id，再把请求的像素复制出来
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        num_instances: u32,
+        camera_bind_group: &wgpu::BindGroup
+    ) {
+        let request = match self.stage {
+            PickStage::Requested(request) => request,
+            _ => return
+        };
+        if model.meshes.len() > self.mesh_capacity {
+            let capacity = model.meshes.len().next_power_of_two();
+            self.mesh_bind_group = Self::create_mesh_bind_group(device, &self.mesh_layout, self.mesh_stride, capacity);
+            self.mesh_capacity = capacity;
+        }
+        {
+            let mut pick_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Pick Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.id_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true
+                    }
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true
+                    }),
+                    stencil_ops: None
+                })
+            });
+            pick_pass.set_pipeline(&self.pipeline);
+            pick_pass.set_bind_group(0, camera_bind_group, &[]);
+            pick_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            // 拾取管线只画三角形，点云选不中，和 Model::intersect_ray 一致
+            for (i, mesh) in model.meshes.iter().enumerate().filter(|(_, m)| m.topology == wgpu::PrimitiveTopology::TriangleList) {
+                let offset = (i as wgpu::BufferAddress * self.mesh_stride) as wgpu::DynamicOffset;
+                pick_pass.set_bind_group(1, &self.mesh_bind_group, &[offset]);
+                pick_pass.set_vertex_buffer(0, mesh.position_buffer.slice(..));
+                mesh.draw_elements(&mut pick_pass, 0..num_instances);
+            }
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.id_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: request.x, y: request.y, z: 0 },
+                aspect: wgpu::TextureAspect::All
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: None
+                }
+            },
+            wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 }
+        );
+        self.stage = PickStage::Encoded(request);
+    }
+
+    // 必须在传给 `encode` 的 encoder 提交之后调用
+    pub fn after_submit(&mut self) {
+        if let PickStage::Encoded(request) = self.stage {
+            let mapped = Arc::new(Mutex::new(None));
+            let sender = mapped.clone();
+            self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                *sender.lock().unwrap() = Some(result);
+            });
+            self.stage = PickStage::Mapping(request, mapped);
+        }
+    }
+
+    // 不阻塞，`Poll::Ready(None)` 表示什么都没选中
+    pub fn poll(&mut self, device: &wgpu::Device, model: &Model) -> Poll<Option<PickResult>> {
+        let (request, mapped) = match &self.stage {
+            PickStage::Mapping(request, mapped) => (*request, mapped.clone()),
+            _ => return Poll::Pending
+        };
+        device.poll(wgpu::Maintain::Poll);
+        let result = match mapped.lock().unwrap().take() {
+            Some(result) => result,
+            None => return Poll::Pending
+        };
+        self.stage = PickStage::Idle;
+        if let Err(e) = result {
+            log::warn!("pick readback failed: {:?}", e);
+            return Poll::Ready(None);
+        }
+
+        let texel = {
+            let view = self.readback_buffer.slice(..).get_mapped_range();
+            let texel: [u32; 4] = bytemuck::pod_read_unaligned(&view[..PICK_TEXEL_SIZE as usize]);
+            texel
+        };
+        self.readback_buffer.unmap();
+
+        let (instance, mesh, depth) = match decode_texel(texel) {
+            Some(hit) => hit,
+            None => return Poll::Ready(None)
+        };
+        // NDC -> 世界空间，用拾取渲染时的大小
+        let (ndc_x, ndc_y) = pixel_to_ndc(request.x, request.y, request.size);
+        let world = request.inv_view_proj * cgmath::Vector4::new(ndc_x, ndc_y, depth, 1.0);
+        let position = Point3::from_homogeneous(world);
+
+        Poll::Ready(Some(PickResult {
+            instance,
+            mesh: model.meshes.get(mesh).map(|m| m.name.clone()).unwrap_or_default(),
+            position
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_texel, pixel_to_ndc, PickMeshUniform};
+    use crate::{instance::InstanceRaw, shaders::reflect::{gpu_fields, ShaderReflection}, vertex::{PositionVertex, Vertex}};
+
+    #[test]
+    fn layouts_match_wgsl() {
+        let shader = ShaderReflection::parse("picking/picking.wgsl", include_str!("picking.wgsl"));
+        shader.check_uniform::<PickMeshUniform>("pick_mesh", &gpu_fields!(PickMeshUniform: index));
+        shader.check_vertex_input("vs_main", &[PositionVertex::desc(), InstanceRaw::desc()]);
+    }
+
+    // 与 picking.wgsl 的 fs_main 相同
+    fn encode_texel(instance_index: u32, mesh: u32, depth: f32) -> [u32; 4] {
+        [instance_index + 1, mesh, depth.to_bits(), 0]
+    }
+
+    #[test]
+    fn ids_round_trip() {
+        for (instance, mesh, depth) in [(0, 0, 0.0), (1, 3, 0.5), (41, 0, 1.0), (u32::MAX - 1, u32::MAX, 0.999_999_9)] {
+            assert_eq!(
+                decode_texel(encode_texel(instance, mesh, depth)),
+                Some((instance as usize, mesh as usize, depth))
+            );
+        }
+        // 清屏颜色表示什么都没选中
+        assert_eq!(decode_texel([0; 4]), None);
+        assert_eq!(decode_texel([0, 5, 1.0f32.to_bits(), 0]), None);
+    }
+
+    #[test]
+    fn pixel_centers_map_into_ndc() {
+        assert_eq!(pixel_to_ndc(0, 0, (2, 2)), (-0.5, 0.5));
+        assert_eq!(pixel_to_ndc(1, 1, (2, 2)), (0.5, -0.5));
+        assert_eq!(pixel_to_ndc(50, 25, (101, 51)), (0.0, 0.0));
+    }
+}
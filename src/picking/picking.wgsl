@@ -0,0 +1,60 @@
+// 顶点着色器
+struct Camera {
+    view_pos: vec4f,
+    view_proj: mat4x4f
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct PickMesh {
+    index: u32,
+    // 补齐到 16 字节对齐
+    _padding_0: u32,
+    _padding_1: u32,
+    _padding_2: u32
+}
+@group(1) @binding(0)
+var<uniform> pick_mesh: PickMesh;
+
+struct VertexInput {
+    @location(0) position: vec3f
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4f,
+    @location(6) model_matrix_1: vec4f,
+    @location(7) model_matrix_2: vec4f,
+    @location(8) model_matrix_3: vec4f,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) @interpolate(flat) instance: u32
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32
+) -> VertexOutput {
+    let model_matrix = mat4x4f(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4f(model.position, 1.0);
+    out.instance = instance_index;
+    return out;
+}
+
+// 片元着色器
+// r: 实例下标 + 1（0 表示什么都没选中）
+// g: 网格下标
+// b: 深度的位，CPU 端用它重建世界空间里的命中点
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<u32> {
+    return vec4<u32>(in.instance + 1u, pick_mesh.index, bitcast<u32>(in.clip_position.z), 0u);
+}
@@ -0,0 +1,310 @@
+use std::{collections::HashMap, hash::Hash, rc::Rc};
+
+// 描述一条渲染管线，相同描述和着色器的管线只会创建一次
+#[derive(Clone)]
+pub struct PipelineBuilder<'a> {
+    label: &'a str,
+    layout: &'a wgpu::PipelineLayout,
+    // WGSL 源码
+    shader: &'a str,
+    vs_entry: &'a str,
+    fs_entry: &'a str,
+    vertex_layouts: Vec<wgpu::VertexBufferLayout<'a>>,
+    color_format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    multisample: wgpu::MultisampleState
+}
+
+impl<'a> PipelineBuilder<'a> {
+    // 默认入口是 `vs_main`/`fs_main`，三角形列表，剔除背面，没有深度，也不混合
+    pub fn new(
+        label: &'a str,
+        layout: &'a wgpu::PipelineLayout,
+        shader: &'a str,
+        color_format: wgpu::TextureFormat
+    ) -> Self {
+        Self {
+            label,
+            layout,
+            shader,
+            vs_entry: "vs_main",
+            fs_entry: "fs_main",
+            vertex_layouts: Vec::new(),
+            color_format,
+            blend: Some(wgpu::BlendState::REPLACE),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default()
+        }
+    }
+
+    pub fn entry_points(mut self, vs_entry: &'a str, fs_entry: &'a str) -> Self {
+        self.vs_entry = vs_entry;
+        self.fs_entry = fs_entry;
+        self
+    }
+
+    pub fn vertex_layouts(mut self, vertex_layouts: &[wgpu::VertexBufferLayout<'a>]) -> Self {
+        self.vertex_layouts = vertex_layouts.to_vec();
+        self
+    }
+
+    // 不能混合的格式传 None，比如整数格式的目标
+    pub fn blend(mut self, blend: Option<wgpu::BlendState>) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.primitive.cull_mode = cull_mode;
+        self
+    }
+
+    // PolygonMode::Line 和 Point 需要设备开启对应的 feature
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.primitive.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn depth(mut self, format: wgpu::TextureFormat, write_enabled: bool, compare: wgpu::CompareFunction) -> Self {
+        self.depth_stencil = Some(wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: write_enabled,
+            depth_compare: compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default()
+        });
+        self
+    }
+
+    pub fn multisample(mut self, multisample: wgpu::MultisampleState) -> Self {
+        self.multisample = multisample;
+        self
+    }
+
+    fn key(&self) -> PipelineKey {
+        PipelineKey {
+            layout: self.layout.global_id(),
+            vs_entry: self.vs_entry.to_owned(),
+            fs_entry: self.fs_entry.to_owned(),
+            vertex_layouts: self.vertex_layouts.iter()
+                .map(|l| (l.array_stride, l.step_mode, l.attributes.to_vec()))
+                .collect(),
+            color_format: self.color_format,
+            blend: self.blend,
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            multisample: self.multisample
+        }
+    }
+
+    // 返回这个描述对应的缓存管线，第一次用到时创建
+    pub fn build(&self, device: &wgpu::Device, cache: &mut PipelineCache) -> Rc<wgpu::RenderPipeline> {
+        let create_shader = || Rc::new(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(self.label),
+            source: wgpu::ShaderSource::Wgsl(self.shader.into())
+        }));
+        let create_pipeline = |shader: &Rc<wgpu::ShaderModule>| Rc::new(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: Some(self.layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: self.vs_entry,
+                buffers: &self.vertex_layouts
+            },
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            multisample: self.multisample,
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: self.fs_entry,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.color_format,
+                    blend: self.blend,
+                    write_mask: wgpu::ColorWrites::ALL
+                })]
+            }),
+            multiview: None
+        }));
+        cache.cache.pipeline(self.shader, self.key(), create_shader, create_pipeline)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    layout: wgpu::Id<wgpu::PipelineLayout>,
+    vs_entry: String,
+    fs_entry: String,
+    vertex_layouts: Vec<(wgpu::BufferAddress, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)>,
+    color_format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    multisample: wgpu::MultisampleState
+}
+
+// 每份不同的着色器源码分到一个 id，管线按 (id, 描述) 缓存。
+// 源码整段比较，不会因为哈希碰撞拿到别的着色器的管线
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct ShaderId(u64);
+
+struct SourceCache<K, S, P> {
+    ids: HashMap<String, ShaderId>,
+    // 删除的 id 不会再分配，避免旧管线被新源码命中
+    next_id: u64,
+    shaders: HashMap<ShaderId, S>,
+    pipelines: HashMap<(ShaderId, K), P>
+}
+
+impl<K, S, P> Default for SourceCache<K, S, P> {
+    fn default() -> Self {
+        Self { ids: HashMap::new(), next_id: 0, shaders: HashMap::new(), pipelines: HashMap::new() }
+    }
+}
+
+impl<K: Eq + Hash, S: Clone, P: Clone> SourceCache<K, S, P> {
+    fn shader_id(&mut self, source: &str) -> ShaderId {
+        if let Some(&id) = self.ids.get(source) {
+            return id;
+        }
+        let id = ShaderId(self.next_id);
+        self.next_id += 1;
+        self.ids.insert(source.to_owned(), id);
+        id
+    }
+
+    fn pipeline<F, G>(&mut self, source: &str, key: K, create_shader: F, create_pipeline: G) -> P
+    where
+        F: FnOnce() -> S,
+        G: FnOnce(&S) -> P
+    {
+        let id = self.shader_id(source);
+        let key = (id, key);
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            return pipeline.clone();
+        }
+        let shader = self.shaders.entry(id).or_insert_with(create_shader).clone();
+        let pipeline = create_pipeline(&shader);
+        self.pipelines.insert(key, pipeline.clone());
+        pipeline
+    }
+
+    fn remove_shader(&mut self, source: &str) {
+        if let Some(id) = self.ids.remove(source) {
+            self.shaders.remove(&id);
+            self.pipelines.retain(|(shader, _), _| *shader != id);
+        }
+    }
+}
+
+// 着色器模块按源码缓存，管线按 PipelineBuilder 的描述缓存
+#[derive(Default)]
+pub struct PipelineCache {
+    cache: SourceCache<PipelineKey, Rc<wgpu::ShaderModule>, Rc<wgpu::RenderPipeline>>
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 删除 `source` 的着色器模块和用它创建的所有管线，比如源码被替换以后
+    pub fn remove_shader(&mut self, source: &str) {
+        self.cache.remove_shader(source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::SourceCache;
+
+    // 管线用 (着色器源码, 变体) 代替，同时数一数创建了几次
+    struct Counting {
+        cache: SourceCache<&'static str, Rc<String>, Rc<(String, &'static str)>>,
+        shaders: Cell<usize>,
+        pipelines: Cell<usize>
+    }
+
+    impl Counting {
+        fn new() -> Self {
+            Self { cache: SourceCache::default(), shaders: Cell::new(0), pipelines: Cell::new(0) }
+        }
+
+        fn build(&mut self, source: &str, variant: &'static str) -> Rc<(String, &'static str)> {
+            let (shaders, pipelines) = (&self.shaders, &self.pipelines);
+            self.cache.pipeline(
+                source,
+                variant,
+                || {
+                    shaders.set(shaders.get() + 1);
+                    Rc::new(source.to_owned())
+                },
+                |shader| {
+                    pipelines.set(pipelines.get() + 1);
+                    Rc::new((shader.to_string(), variant))
+                }
+            )
+        }
+
+        fn counts(&self) -> (usize, usize) {
+            (self.shaders.get(), self.pipelines.get())
+        }
+
+        fn remove_shader_and_check(&mut self, source: &str) {
+            self.cache.remove_shader(source);
+            assert!(!self.cache.ids.contains_key(source));
+            assert!(self.cache.pipelines.keys().all(|(id, _)| self.cache.shaders.contains_key(id)));
+        }
+    }
+
+    #[test]
+    fn same_key_reuses_the_pipeline() {
+        let mut cache = Counting::new();
+        let first = cache.build("shader a", "opaque");
+        let second = cache.build(&String::from("shader a"), "opaque");
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(cache.counts(), (1, 1));
+        // 同一个着色器的另一个变体共用模块
+        let blend = cache.build("shader a", "blend");
+        assert_eq!(*blend, ("shader a".to_owned(), "blend"));
+        assert_eq!(cache.counts(), (1, 2));
+        // 不同源码不会共用，即使变体相同
+        assert_eq!(*cache.build("shader b", "opaque"), ("shader b".to_owned(), "opaque"));
+        assert_eq!(cache.counts(), (2, 3));
+    }
+
+    #[test]
+    fn remove_shader_evicts_every_variant() {
+        let mut cache = Counting::new();
+        let old = ["opaque", "mask", "blend"].map(|variant| cache.build("shader a", variant));
+        let other = cache.build("shader b", "opaque");
+        assert_eq!(cache.counts(), (2, 4));
+
+        cache.remove_shader_and_check("shader a");
+        for (variant, old) in ["opaque", "mask", "blend"].into_iter().zip(&old) {
+            assert!(!Rc::ptr_eq(&cache.build("shader a", variant), old));
+        }
+        assert_eq!(cache.counts(), (3, 7));
+        assert!(Rc::ptr_eq(&cache.build("shader b", "opaque"), &other));
+        // 删除不存在的源码什么都不做
+        cache.remove_shader_and_check("shader c");
+        assert_eq!(cache.counts(), (3, 7));
+    }
+}
@@ -0,0 +1,226 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+// 射线相交时允许的最小距离，避免与起点所在的面自相交
+const EPSILON: f32 = 1e-6;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    // 不要求是单位向量，transform 之后仍保持与原射线相同的参数 t
+    pub direction: Vector3<f32>
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self { origin, direction }
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+
+    // 方向不重新归一化，变换后的空间里 `t` 处的交点
+    // 就是原来的射线上 `t` 处的交点
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        Self {
+            origin: matrix.transform_point(self.origin),
+            direction: matrix.transform_vector(self.direction)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>
+}
+
+impl Aabb {
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self { min: first, max: first }, |aabb, p| Self {
+            min: Point3::new(aabb.min.x.min(p.x), aabb.min.y.min(p.y), aabb.min.z.min(p.z)),
+            max: Point3::new(aabb.max.x.max(p.x), aabb.max.y.max(p.y), aabb.max.z.max(p.z))
+        }))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    // 包围盒的外接球半径
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).magnitude() * 0.5
+    }
+}
+
+// slab 测试，返回到进入点的距离，起点在盒子里时返回 0
+pub fn intersect_aabb(ray: &Ray, aabb: &Aabb) -> Option<f32> {
+    let mut t_min = 0.0_f32;
+    let mut t_max = f32::INFINITY;
+    for axis in 0..3 {
+        let origin = ray.origin[axis];
+        let direction = ray.direction[axis];
+        if direction.abs() < EPSILON {
+            // 与该轴的平面平行，起点必须在 slab 之内
+            if origin < aabb.min[axis] || origin > aabb.max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let inv = 1.0 / direction;
+        let mut t0 = (aabb.min[axis] - origin) * inv;
+        let mut t1 = (aabb.max[axis] - origin) * inv;
+        if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        t_min = t_min.max(t0);
+        t_max = t_max.min(t1);
+        if t_min > t_max {
+            return None;
+        }
+    }
+    Some(t_min)
+}
+
+// 返回最近的非负距离，起点在球里时返回 0
+pub fn intersect_sphere(ray: &Ray, center: Point3<f32>, radius: f32) -> Option<f32> {
+    let oc = ray.origin - center;
+    let a = ray.direction.magnitude2();
+    if a < EPSILON * EPSILON {
+        return None;
+    }
+    let half_b = oc.dot(ray.direction);
+    let c = oc.magnitude2() - radius * radius;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = (-half_b - discriminant.sqrt()) / a;
+    if t >= 0.0 { Some(t) } else { None }
+}
+
+// Möller–Trumbore，不区分正反面。返回距离和交点的重心坐标 (u, v)
+pub fn intersect_triangle(
+    ray: &Ray,
+    a: Point3<f32>,
+    b: Point3<f32>,
+    c: Point3<f32>
+) -> Option<(f32, f32, f32)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < EPSILON {
+        // 射线与三角形平行或三角形退化
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = ray.origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inv_det;
+    if t > EPSILON { Some((t, u, v)) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, Point3, SquareMatrix, Vector3};
+
+    use super::{intersect_aabb, intersect_sphere, intersect_triangle, Aabb, Ray};
+
+    fn unit_box() -> Aabb {
+        Aabb { min: Point3::new(0.0, 0.0, 0.0), max: Point3::new(1.0, 1.0, 1.0) }
+    }
+
+    #[test]
+    fn aabb_hits_and_misses() {
+        let aabb = unit_box();
+        // 与 y、z 的 slab 平行，起点在这两个 slab 之内
+        assert_eq!(intersect_aabb(&Ray::new(Point3::new(-5.0, 0.5, 0.5), Vector3::unit_x()), &aabb), Some(5.0));
+        assert_eq!(intersect_aabb(&Ray::new(Point3::new(-5.0, 0.5, 0.5), -Vector3::unit_x()), &aabb), None);
+        // 平行但在 slab 之外
+        assert_eq!(intersect_aabb(&Ray::new(Point3::new(-5.0, 2.0, 0.5), Vector3::unit_x()), &aabb), None);
+        assert_eq!(intersect_aabb(&Ray::new(Point3::new(-1.0, -1.0, 0.5), Vector3::new(1.0, 1.0, 0.0)), &aabb), Some(1.0));
+        assert_eq!(intersect_aabb(&Ray::new(Point3::new(-1.0, 0.0, 0.5), Vector3::new(1.0, 3.0, 0.0)), &aabb), None);
+    }
+
+    #[test]
+    fn aabb_origin_inside() {
+        let aabb = unit_box();
+        assert_eq!(intersect_aabb(&Ray::new(Point3::new(0.5, 0.5, 0.5), Vector3::unit_z()), &aabb), Some(0.0));
+        assert_eq!(intersect_aabb(&Ray::new(Point3::new(0.5, 0.5, 0.5), Vector3::new(-1.0, 2.0, 3.0)), &aabb), Some(0.0));
+    }
+
+    #[test]
+    fn sphere_hits_and_misses() {
+        let center = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(intersect_sphere(&Ray::new(Point3::new(0.0, 0.0, -5.0), Vector3::unit_z()), center, 1.0), Some(4.0));
+        // 方向不是单位向量时 t 按原方向计算
+        assert_eq!(intersect_sphere(&Ray::new(Point3::new(0.0, 0.0, -5.0), Vector3::unit_z() * 2.0), center, 1.0), Some(2.0));
+        assert_eq!(intersect_sphere(&Ray::new(Point3::new(2.0, 0.0, -5.0), Vector3::unit_z()), center, 1.0), None);
+        // 球在射线背后
+        assert_eq!(intersect_sphere(&Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::unit_z()), center, 1.0), None);
+        assert_eq!(intersect_sphere(&Ray::new(Point3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 0.0)), center, 1.0), None);
+    }
+
+    #[test]
+    fn sphere_origin_inside() {
+        let center = Point3::new(1.0, 2.0, 3.0);
+        assert_eq!(intersect_sphere(&Ray::new(center, Vector3::unit_x()), center, 1.0), Some(0.0));
+        assert_eq!(intersect_sphere(&Ray::new(Point3::new(1.5, 2.0, 3.0), -Vector3::unit_y()), center, 1.0), Some(0.0));
+    }
+
+    #[test]
+    fn triangle_hits_and_misses() {
+        let (a, b, c) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(
+            intersect_triangle(&Ray::new(Point3::new(0.25, 0.5, -2.0), Vector3::unit_z()), a, b, c),
+            Some((2.0, 0.25, 0.5))
+        );
+        // 双面
+        assert_eq!(
+            intersect_triangle(&Ray::new(Point3::new(0.25, 0.5, 2.0), -Vector3::unit_z()), a, b, c),
+            Some((2.0, 0.25, 0.5))
+        );
+        assert_eq!(intersect_triangle(&Ray::new(Point3::new(0.75, 0.75, -2.0), Vector3::unit_z()), a, b, c), None);
+        assert_eq!(intersect_triangle(&Ray::new(Point3::new(0.25, 0.25, 2.0), Vector3::unit_z()), a, b, c), None);
+    }
+
+    #[test]
+    fn triangle_parallel_or_degenerate() {
+        let (a, b, c) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0));
+        // 在三角形所在平面内
+        assert_eq!(intersect_triangle(&Ray::new(Point3::new(-1.0, 0.25, 0.0), Vector3::unit_x()), a, b, c), None);
+        assert_eq!(intersect_triangle(&Ray::new(Point3::new(-1.0, 0.25, 0.5), Vector3::unit_x()), a, b, c), None);
+        // 三点共线和两点重合
+        let ray = Ray::new(Point3::new(0.5, 0.0, -1.0), Vector3::unit_z());
+        assert_eq!(intersect_triangle(&ray, a, b, Point3::new(2.0, 0.0, 0.0)), None);
+        assert_eq!(intersect_triangle(&ray, a, a, c), None);
+    }
+
+    #[test]
+    fn transform_keeps_the_ray_parameter() {
+        // 世界空间里中心 (10, 0, 0) 半径 2 的球，是模型空间的单位球
+        let model = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0)) * Matrix4::from_scale(2.0);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::unit_x());
+        let world_t = intersect_sphere(&ray, Point3::new(10.0, 0.0, 0.0), 2.0).unwrap();
+        let local = ray.transform(&model.invert().unwrap());
+        assert_eq!(local.origin, Point3::new(-5.0, 0.0, 0.0));
+        assert_eq!(local.direction, Vector3::new(0.5, 0.0, 0.0));
+        let local_t = intersect_sphere(&local, Point3::new(0.0, 0.0, 0.0), 1.0).unwrap();
+        assert_eq!(world_t, 8.0);
+        assert_eq!(local_t, world_t);
+    }
+}
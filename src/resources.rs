@@ -1,79 +1,238 @@
-use std::io::{BufReader, Cursor};
-use anyhow::Ok;
-use cfg_if::cfg_if;
-use wgpu::util::DeviceExt;
-
-use crate::{texture, model};
-
-#[cfg(target_arch = "wasm32")]
-fn format_url(file_name: &str) -> reqwest::Url {
-    let window = web_sys::window().unwrap();
-    let location = window.location();
-    let base = reqwest::Url::parse(&format!(
-        "{}/{}/",
-        location.origin().unwrap(),
-        option_env!("RES_PATH").unwrap_or("res")
-    )).unwrap();
-    base.join(file_name).unwrap()
-}
-
-pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let txt = reqwest::get(url)
-                .await?
-                .text()
-                .await?;
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
-        }
+use std::{cell::RefCell, io::{BufReader, Cursor}};
+use crate::{bake, geometry, import, texture, vfs, error::{Error, Result}, model::{self, AlphaMode}};
+
+// Mask 模式下默认的 alpha 阈值
+const DEFAULT_ALPHA_CUTOFF: f32 = 0.5;
+// 生成法线时，面之间夹角超过它的边保留为硬边
+const CREASE_ANGLE: cgmath::Deg<f32> = cgmath::Deg(60.0);
+
+// 没有贴图时用的 1x1 贴图的名字，不是文件
+const DEFAULT_DIFFUSE_TEXTURE: &str = "default_diffuse";
+const DEFAULT_NORMAL_TEXTURE: &str = "default_normal";
+
+// 加载网格后的可选处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshProcessing {
+    // 合并完全相同的顶点
+    pub weld: bool,
+    // 按顶点缓存和 overdraw 重排三角形，按第一次使用重排顶点
+    pub optimize: bool,
+    // 顶点数允许时用 16 位索引
+    pub compact_indices: bool
+}
+
+impl Default for MeshProcessing {
+    fn default() -> Self {
+        Self { weld: true, optimize: true, compact_indices: true }
+    }
+}
+
+// 环境变量，设置后开启模型和贴图的热加载
+const HOT_RELOAD_ENV: &str = "ASSET_HOT_RELOAD";
+
+// Web 上不支持热加载
+pub fn hot_reload() -> bool {
+    cfg!(not(target_arch = "wasm32")) && std::env::var_os(HOT_RELOAD_ENV).is_some()
+}
+
+// 资源文件的修改时间，用于热加载
+pub fn modified(file_name: &str) -> Option<std::time::SystemTime> {
+    vfs::get().modified(file_name)
+}
+
+// 按 vfs 里的资源根顺序查找
+pub async fn load_string(file_name: &str) -> Result<String> {
+    String::from_utf8(load_binary(file_name).await?).map_err(|source| Error::InvalidText { file: file_name.to_owned(), source })
+}
+
+pub async fn load_binary(file_name: &str) -> Result<Vec<u8>> {
+    vfs::get().read(file_name).await
+}
+
+// 返回贴图在 `textures` 中的下标，同一个模型里多个材质共用的贴图只读取和解码一次
+async fn load_texture(
+    file_name: &str,
+    is_normal_map: bool,
+    textures: &mut Vec<texture::TextureData>,
+    on_file: &mut dyn FnMut(&str, &[u8])
+) -> Result<usize> {
+    // MTL 里没有写贴图时用白色和朝外的法线，效果和没有贴图一样
+    let file_name = match (file_name, is_normal_map) {
+        ("", false) => DEFAULT_DIFFUSE_TEXTURE,
+        ("", true) => DEFAULT_NORMAL_TEXTURE,
+        _ => file_name
+    };
+    if let Some(index) = textures.iter().position(|t| t.label == file_name && t.is_normal_map == is_normal_map) {
+        return Ok(index);
+    }
+    if file_name == DEFAULT_DIFFUSE_TEXTURE || file_name == DEFAULT_NORMAL_TEXTURE {
+        let rgba = if is_normal_map { [128, 128, 255, 255] } else { [255; 4] };
+        textures.push(texture::TextureData::solid(file_name, rgba, is_normal_map));
+        return Ok(textures.len() - 1);
     }
+    let data = load_binary(file_name).await?;
+    on_file(file_name, &data);
+    let image = image::load_from_memory(&data).map_err(|source| match source {
+        image::ImageError::Unsupported(e) => Error::UnsupportedFormat { file: file_name.to_owned(), reason: e.to_string() },
+        source => Error::DecodeImage { file: file_name.to_owned(), source }
+    })?;
+    textures.push(texture::TextureData::from_image(&image, file_name, is_normal_map));
+    Ok(textures.len() - 1)
+}
 
-    Ok(txt)
-}
-pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let data = reqwest::get(url)
-                .await?
-                .bytes()
-                .await?
-                .to_vec();
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let data = std::fs::read(path)?;
+// MTL 没有表示 alpha 测试的语句，可以写非标准的 `alpha_mode opaque|mask|blend`，
+// 否则 d < 1 时混合，再根据漫反射贴图的 alpha 判断：只有 0 和 1 时用 Mask，有中间值时用 Blend
+fn alpha_mode(m: &tobj::Material, diffuse: &texture::TextureData) -> AlphaMode {
+    match m.unknown_param.get("alpha_mode").map(|s| s.trim()) {
+        Some("opaque") => return AlphaMode::Opaque,
+        Some("mask") => return AlphaMode::Mask,
+        Some("blend") => return AlphaMode::Blend,
+        Some(other) => log::warn!("{}: unknown alpha_mode {}", m.name, other),
+        None => {}
+    }
+    if m.dissolve < 1.0 {
+        return AlphaMode::Blend;
+    }
+    let mut mode = AlphaMode::Opaque;
+    for pixel in diffuse.mips[0].chunks_exact(4) {
+        match pixel[3] {
+            255 => {}
+            0 => mode = AlphaMode::Mask,
+            _ => return AlphaMode::Blend
         }
     }
+    mode
+}
 
-    Ok(data)
+// Blender 导出的 Kd 在有 map_Kd 时就是贴图本身的颜色，这时不再乘一次
+fn material_uniform(m: &tobj::Material, alpha_mode: AlphaMode) -> model::MaterialUniform {
+    let diffuse = if m.diffuse_texture.is_empty() { m.diffuse } else { [1.0; 3] };
+    let emissive = m.unknown_param.get("Ke")
+        .map(|v| {
+            let mut rgb = [0.0; 3];
+            for (c, s) in rgb.iter_mut().zip(v.split_whitespace()) {
+                *c = s.parse().unwrap_or(0.0);
+            }
+            rgb
+        })
+        .unwrap_or([0.0; 3]);
+    let alpha_cutoff = match alpha_mode {
+        AlphaMode::Mask => m.unknown_param.get("alpha_cutoff")
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(DEFAULT_ALPHA_CUTOFF),
+        _ => 0.0
+    };
+    model::MaterialUniform {
+        diffuse: [diffuse[0], diffuse[1], diffuse[2], m.dissolve],
+        emissive,
+        alpha_cutoff
+    }
 }
 
-pub async fn load_texture(
+// 模型加载完成前代替它显示的灰色立方体
+pub fn placeholder_model() -> model::ModelData {
+    let vertices = [
+        [-0.5, -0.5, -0.5], [0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [-0.5, 0.5, -0.5],
+        [-0.5, -0.5, 0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5], [-0.5, 0.5, 0.5]
+    ].map(|position| model::ModelVertex { position, ..Default::default() });
+    let indices = [
+        4, 5, 6, 4, 6, 7, 1, 0, 3, 1, 3, 2, 5, 1, 2, 5, 2, 6,
+        0, 4, 7, 0, 7, 3, 7, 6, 2, 7, 2, 3, 0, 1, 5, 0, 5, 4
+    ];
+    let (vertices, indices) = geometry::generate_normals(&vertices, &indices, cgmath::Rad(0.0));
+    let (vertices, indices, _) = geometry::generate_uvs(&vertices, &indices);
+    let (vertices, indices) = geometry::generate_tangents(&vertices, &indices);
+    model::ModelData {
+        meshes: vec![model::MeshData {
+            name: "placeholder".to_owned(),
+            vertices,
+            indices,
+            index_format: wgpu::IndexFormat::Uint16,
+            material: 0
+        }],
+        materials: vec![model::MaterialData {
+            name: "placeholder".to_owned(),
+            diffuse_texture: 0,
+            normal_texture: 1,
+            uniform: model::MaterialUniform::default(),
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false
+        }],
+        textures: vec![
+            texture::TextureData::solid("placeholder_diffuse", [160, 160, 160, 255], false),
+            // 切线空间的 (0, 0, 1)
+            texture::TextureData::solid("placeholder_normal", [128, 128, 255, 255], true)
+        ]
+    }
+}
+
+// 有和加载参数一致、且源文件没有变过的烘焙文件时直接读取，否则从 OBJ/MTL 和图片加载。
+// 不需要 GPU，可以在工作线程上运行，见 loader::AssetLoader
+pub async fn read_model(
     file_name: &str,
-    is_normal_map: bool,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue
-) -> anyhow::Result<texture::Texture> {
+    processing: &MeshProcessing,
+    on_file: &mut dyn FnMut(&str, &[u8])
+) -> Result<model::ModelData> {
+    match bake::load(file_name, processing, on_file).await {
+        Some(data) => Ok(data),
+        None => load_model_data(file_name, processing, on_file).await
+    }
+}
+
+// 从源文件加载并处理模型，按扩展名选择 OBJ、PLY 或 STL。
+// 每读完一个文件调用一次 `on_file`，用于烘焙时记录源文件和显示加载进度
+pub async fn load_model_data(
+    file_name: &str,
+    processing: &MeshProcessing,
+    on_file: &mut dyn FnMut(&str, &[u8])
+) -> Result<model::ModelData> {
+    let extension = file_name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+    let parse = match extension.as_str() {
+        "obj" => return load_obj(file_name, processing, on_file).await,
+        "ply" => import::parse_ply,
+        "stl" => import::parse_stl,
+        _ => return Err(Error::UnsupportedFormat { file: file_name.to_owned(), reason: "expected .obj, .ply or .stl".to_owned() })
+    };
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    on_file(file_name, &data);
+    let mesh = parse(file_name, &data)?;
+    let mut textures = Vec::new();
+    let material = default_material(&mut textures).await?;
+    let name = if mesh.name.is_empty() { file_name.to_owned() } else { mesh.name.clone() };
+    if mesh.indices.is_empty() {
+        let mesh = point_cloud(name, &mesh);
+        log::info!("{} {:?}: no faces, drawing {} vertices as points", file_name, mesh.name, mesh.vertices.len());
+        return Ok(model::ModelData { meshes: vec![mesh], materials: vec![material], textures });
+    }
+    let vertices = (0..mesh.positions.len()).map(|i| model::ModelVertex {
+        position: mesh.positions[i],
+        tex_coords: mesh.tex_coords.as_ref().map_or([0.0; 2], |t| t[i]),
+        normal: mesh.normals.as_ref().map_or([0.0; 3], |n| n[i]),
+        tangent: [0.0; 4],
+        color: mesh.colors.as_ref().map_or([1.0; 4], |c| vertex_color(c[i]))
+    }).collect::<Vec<_>>();
+    let (vertices, indices) = if mesh.normals.is_some() {
+        (vertices, mesh.indices)
+    } else {
+        // STL 每个三角形的顶点是分开的，先合并相同的位置，生成法线时才能平滑
+        geometry::weld(&vertices, &mesh.indices)
+    };
+    let mesh = process_mesh(file_name, name, vertices, indices, mesh.normals.is_some(), mesh.tex_coords.is_some(), processing);
+    Ok(model::ModelData { meshes: vec![mesh], materials: vec![material], textures })
 }
 
-pub async fn load_model(
+async fn load_obj(
     file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    layout: &wgpu::BindGroupLayout
-) -> anyhow::Result<model::Model> {
+    processing: &MeshProcessing,
+    on_file: &mut dyn FnMut(&str, &[u8])
+) -> Result<model::ModelData> {
     let obj_text = load_string(file_name).await?;
+    on_file(file_name, obj_text.as_bytes());
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
+    let on_mtl_file = RefCell::new(&mut *on_file);
+    // tobj 的回调只能返回它自己的错误，读取 MTL 的错误记在这里
+    let mtl_error = RefCell::new(None);
 
     let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader, 
@@ -82,118 +241,270 @@ pub async fn load_model(
             triangulate: true, 
             ..Default::default()
         }, 
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let (on_mtl_file, mtl_error) = (&on_mtl_file, &mtl_error);
+            async move {
+                let mat_text = match load_string(&p).await {
+                    Ok(text) => text,
+                    Err(e) => {
+                        *mtl_error.borrow_mut() = Some(e);
+                        return Err(tobj::LoadError::OpenFileFailed);
+                    }
+                };
+                on_mtl_file.borrow_mut()(&p, mat_text.as_bytes());
+                tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+                    .inspect_err(|&source| *mtl_error.borrow_mut() = Some(Error::Obj { file: p.clone(), source }))
+            }
         }
-    ).await?;
+    ).await.map_err(|source| Error::Obj { file: file_name.to_owned(), source })?;
 
+    let obj_materials = obj_materials.map_err(|source| {
+        mtl_error.take().unwrap_or(Error::Obj { file: file_name.to_owned(), source })
+    })?;
     let mut materials = Vec::new();
-    for m in obj_materials? {
+    let mut textures = Vec::new();
+    for m in obj_materials {
         // println!("material {}", &m.diffuse_texture);
-        let diffuse_texture = load_texture(&m.diffuse_texture, false, device, queue).await?;
-        let normal_texture = load_texture(&m.normal_texture, true, device, queue).await?;
-
-        materials.push(model::Material::new(
-            device,
-            &m.name,
+        let diffuse_texture = load_texture(&m.diffuse_texture, false, &mut textures, on_file).await?;
+        let normal_texture = load_texture(&m.normal_texture, true, &mut textures, on_file).await?;
+        // alpha 模式要先看过漫反射贴图
+        let alpha_mode = alpha_mode(&m, &textures[diffuse_texture]);
+        materials.push(model::MaterialData {
             diffuse_texture,
             normal_texture,
-            layout
-        ));
+            uniform: material_uniform(&m, alpha_mode),
+            alpha_mode,
+            // 非标准的 `double_sided 1`
+            double_sided: m.unknown_param.get("double_sided").is_some_and(|v| v.trim() == "1"),
+            name: m.name
+        });
+    }
+
+    // 没有材质或者引用的材质不存在的网格用默认材质
+    let material_count = materials.len();
+    let mut default_index = None;
+    if models.iter().any(|m| m.mesh.material_id.is_none_or(|id| id >= material_count)) {
+        materials.push(default_material(&mut textures).await?);
+        default_index = Some(materials.len() - 1);
     }
 
     let meshes = models.into_iter().map(|m| {
         // println!("model.name = \'{}\'", m.name);
         // println!("model.mesh.material_id = {:?}", m.mesh.material_id);
-        let mut vertices = (0..m.mesh.positions.len() / 3).map(|i| model::ModelVertex {
+        let num_vertices = m.mesh.positions.len() / 3;
+        if let Some(&index) = m.mesh.indices.iter().find(|&&i| i as usize >= num_vertices) {
+            return Err(Error::InvalidIndex { file: file_name.to_owned(), mesh: m.name, index, vertex_count: num_vertices });
+        }
+        // 没有 vt、vn 的 OBJ 之后再生成
+        let has_tex_coords = m.mesh.texcoords.len() == num_vertices * 2;
+        let has_normals = m.mesh.normals.len() == num_vertices * 3;
+        // 非标准的 `v x y z r g b`
+        let has_colors = m.mesh.vertex_color.len() == num_vertices * 3;
+        let vertices = (0..num_vertices).map(|i| model::ModelVertex {
             position: [
                 m.mesh.positions[i*3],
                 m.mesh.positions[i*3+1],
                 m.mesh.positions[i*3+2],
             ],
-            tex_coords: [m.mesh.texcoords[i*2], m.mesh.texcoords[i*2+1]],
-            normal: [
-                m.mesh.normals[i*3],
-                m.mesh.normals[i*3+1],
-                m.mesh.normals[i*3+2],
-            ],
+            tex_coords: if has_tex_coords { [m.mesh.texcoords[i*2], m.mesh.texcoords[i*2+1]] } else { [0.0; 2] },
+            normal: if has_normals {
+                [
+                    m.mesh.normals[i*3],
+                    m.mesh.normals[i*3+1],
+                    m.mesh.normals[i*3+2],
+                ]
+            } else {
+                [0.0; 3]
+            },
             // compute later
-            tangent: [0.0; 3],
-            bitangent: [0.0; 3],            
+            tangent: [0.0; 4],
+            color: if has_colors {
+                vertex_color([m.mesh.vertex_color[i*3], m.mesh.vertex_color[i*3+1], m.mesh.vertex_color[i*3+2], 1.0])
+            } else {
+                [1.0; 4]
+            }
         }).collect::<Vec<_>>();
+        let material = m.mesh.material_id.filter(|&id| id < material_count).or(default_index).unwrap_or(0);
+        Ok(model::MeshData {
+            material,
+            ..process_mesh(file_name, m.name, vertices, m.mesh.indices, has_normals, has_tex_coords, processing)
+        })
+    }).collect::<Result<Vec<_>>>()?;
 
-        let indices = &m.mesh.indices;
-        let mut triangles_included = vec![0; vertices.len()];
-
-        for c in indices.chunks(3) {
-            let v0 = vertices[c[0] as usize];
-            let v1 = vertices[c[1] as usize];
-            let v2 = vertices[c[2] as usize];
-            
-            let pos0: cgmath::Vector3<f32> = v0.position.into();
-            let pos1: cgmath::Vector3<f32> = v1.position.into();
-            let pos2: cgmath::Vector3<f32> = v2.position.into();
-
-            let uv0: cgmath::Vector2<f32> = v0.tex_coords.into();
-            let uv1: cgmath::Vector2<f32> = v1.tex_coords.into();
-            let uv2: cgmath::Vector2<f32> = v2.tex_coords.into();
-
-            let delta_pos1 = pos1 - pos0;
-            let delta_pos2 = pos2 - pos0;
-            let delta_uv1 = uv1 - uv0;
-            let delta_uv2 = uv2 - uv0;
-
-            let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-            let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-            let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-            vertices[c[0] as usize].tangent =
-                (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-            vertices[c[1] as usize].tangent =
-                (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-            vertices[c[2] as usize].tangent =
-                (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-            vertices[c[0] as usize].bitangent =
-                (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-            vertices[c[1] as usize].bitangent =
-                (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-            vertices[c[2] as usize].bitangent =
-                (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-
-            triangles_included[c[0] as usize] += 1;
-            triangles_included[c[1] as usize] += 1;
-            triangles_included[c[2] as usize] += 1;
-        }
+    Ok(model::ModelData { 
+        meshes, 
+        materials,
+        textures
+    })
+}
 
-        for (i, n) in triangles_included.into_iter().enumerate() {
-            let denom = 1.0 / n as f32;
-            let v = &mut vertices[i];
-            v.tangent = (cgmath::Vector3::from(v.tangent)*denom).into();
-            v.bitangent = (cgmath::Vector3::from(v.bitangent)*denom).into();
-        }
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Vertex Buffer", m.name)),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Index Buffer", m.name)),
-            contents: bytemuck::cast_slice(&m.mesh.indices),
-            usage: wgpu::BufferUsages::INDEX
-        });
+// 点云没有三角形，不能生成法线和切线，也不做焊接和重排，缺少的属性用 ModelVertex 的默认值
+fn point_cloud(name: String, mesh: &import::ImportedMesh) -> model::MeshData {
+    let default = model::ModelVertex::default();
+    let vertices = (0..mesh.positions.len()).map(|i| model::ModelVertex {
+        position: mesh.positions[i],
+        tex_coords: mesh.tex_coords.as_ref().map_or(default.tex_coords, |t| t[i]),
+        normal: mesh.normals.as_ref().map_or(default.normal, |n| n[i]),
+        tangent: default.tangent,
+        color: mesh.colors.as_ref().map_or(default.color, |c| vertex_color(c[i]))
+    }).collect();
+    model::MeshData { name, vertices, indices: Vec::new(), index_format: wgpu::IndexFormat::Uint32, material: 0 }
+}
 
-        model::Mesh {
-            name: m.name,
-            vertex_buffer,
-            index_buffer,
-            num_elements: m.mesh.indices.len() as u32,
-            material: m.mesh.material_id.unwrap_or(0)
-        }
-    }).collect::<Vec<_>>();
+// 文件里的顶点颜色和图片一样是 sRGB，着色器里按线性颜色相乘
+fn vertex_color([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    let linear = |c: f32| if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+    [linear(r), linear(g), linear(b), a]
+}
 
-    Ok(model::Model { 
-        meshes, 
-        materials 
+// 白色、不透明，贴图见 load_texture
+async fn default_material(textures: &mut Vec<texture::TextureData>) -> Result<model::MaterialData> {
+    Ok(model::MaterialData {
+        name: "default".to_owned(),
+        diffuse_texture: load_texture("", false, textures, &mut |_, _| {}).await?,
+        normal_texture: load_texture("", true, textures, &mut |_, _| {}).await?,
+        uniform: model::MaterialUniform::default(),
+        alpha_mode: AlphaMode::Opaque,
+        double_sided: false
     })
-}
\ No newline at end of file
+}
+
+// 生成缺少的法线、纹理坐标和切线，再按 `processing` 处理。材质留给调用者设置
+fn process_mesh(
+    file_name: &str,
+    name: String,
+    vertices: Vec<model::ModelVertex>,
+    indices: Vec<u32>,
+    has_normals: bool,
+    has_tex_coords: bool,
+    processing: &MeshProcessing
+) -> model::MeshData {
+    let (vertices, indices) = if has_normals {
+        (vertices, indices)
+    } else {
+        log::info!("{} {:?}: no normals, generating smooth normals with a {:?} crease angle", file_name, name, CREASE_ANGLE);
+        geometry::generate_normals(&vertices, &indices, CREASE_ANGLE.into())
+    };
+    let (vertices, indices) = if has_tex_coords {
+        (vertices, indices)
+    } else {
+        let (vertices, indices, projection) = geometry::generate_uvs(&vertices, &indices);
+        log::info!("{} {:?}: no texture coordinates, generated them with {:?} projection", file_name, name, projection);
+        (vertices, indices)
+    };
+    let (vertices, indices) = geometry::generate_tangents(&vertices, &indices);
+    // tobj 按 (v, vt, vn) 组合去重，生成法线和切线后可能还有完全相同的顶点
+    let (vertices, indices) = if processing.weld {
+        geometry::weld(&vertices, &indices)
+    } else {
+        (vertices, indices)
+    };
+    let (vertices, indices) = if processing.optimize {
+        geometry::optimize(&vertices, &indices)
+    } else {
+        (vertices, indices)
+    };
+    let index_format = if processing.compact_indices && vertices.len() <= u16::MAX as usize + 1 {
+        wgpu::IndexFormat::Uint16
+    } else {
+        wgpu::IndexFormat::Uint32
+    };
+    model::MeshData { name, vertices, indices, index_format, material: 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::{load_model_data, MeshProcessing};
+    use crate::error::{Error, Result};
+
+    // 写到 OUT_DIR/res 里加载，再删掉
+    fn load(name: &str, obj: &str) -> Result<crate::model::ModelData> {
+        let path = std::path::Path::new(env!("OUT_DIR")).join("res").join(name);
+        std::fs::write(&path, obj).unwrap();
+        let result = pollster::block_on(load_model_data(name, &MeshProcessing::default(), &mut |_, _| {}));
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn errors_name_the_file_and_the_problem() {
+        let error = load("errors_bad_index.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 9\n").unwrap_err();
+        // tobj 自己检查了面的索引
+        assert!(matches!(&error, Error::Obj { file, source: tobj::LoadError::FaceVertexOutOfBounds } if file == "errors_bad_index.obj"), "{}", error.report());
+        let error = load("errors_missing_mtl.obj", "mtllib errors_missing.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n").unwrap_err();
+        assert!(matches!(&error, Error::NotFound { file, .. } if file == "errors_missing.mtl"), "{}", error.report());
+        let error = pollster::block_on(load_model_data("cube.fbx", &MeshProcessing::default(), &mut |_, _| {})).unwrap_err();
+        assert!(matches!(&error, Error::UnsupportedFormat { file, .. } if file == "cube.fbx"), "{}", error.report());
+        let error = load("errors_bad_index.ply", "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n1 1 0\n3 0 1 3\n").unwrap_err();
+        assert!(matches!(&error, Error::InvalidIndex { index: 3, vertex_count: 3, .. }), "{}", error.report());
+    }
+
+    #[test]
+    fn stl_gets_smooth_normals_tangents_and_the_default_material() {
+        // 四面体，每个三角形的顶点分开存放
+        let corners = ["0 0 0", "1 0 0", "0 1 0", "0 0 1"];
+        let faces = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+        let mut stl = "solid tetrahedron\n".to_owned();
+        for face in faces {
+            stl += "facet normal 0 0 0\nouter loop\n";
+            for i in face {
+                stl += &format!("vertex {}\n", corners[i]);
+            }
+            stl += "endloop\nendfacet\n";
+        }
+        let model = load("tetrahedron.stl", &stl).unwrap();
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.name, "tetrahedron");
+        assert_eq!(mesh.indices.len(), 12);
+        // 顶点焊接后按 60° 的折痕角，四面体的棱都是硬边，每个角每个面一个顶点
+        assert_eq!(mesh.vertices.len(), 12);
+        assert!(mesh.vertices.iter().all(|v| (cgmath::Vector3::from(v.normal).magnitude() - 1.0).abs() < 1e-4 && v.tangent[3].abs() == 1.0));
+        assert_eq!(model.materials.len(), 1);
+        assert_eq!(model.materials[mesh.material].name, "default");
+        assert_eq!(model.textures.len(), 2);
+    }
+
+    #[test]
+    fn vertex_only_ply_is_a_point_cloud() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nend_header\n0 0 0 255 0 0\n1 0 0 255 0 0\n1 0 0 0 0 255\n";
+        let model = load("points.ply", ply).unwrap();
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.topology(), wgpu::PrimitiveTopology::PointList);
+        assert!(mesh.indices.is_empty());
+        // 重复的点也保留，不焊接
+        assert_eq!(mesh.vertices.iter().map(|v| (v.position, v.color)).collect::<Vec<_>>(), [
+            ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 1.0]),
+            ([1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 1.0]),
+            ([1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 1.0])
+        ]);
+        assert!(mesh.vertices.iter().all(|v| v.normal == crate::model::ModelVertex::default().normal));
+        assert_eq!(model.materials[mesh.material].name, "default");
+    }
+
+    #[test]
+    fn obj_without_materials_uses_the_default_material() {
+        let model = load("no_materials.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n").unwrap();
+        assert_eq!(model.materials[model.meshes[0].material].name, "default");
+        assert_eq!(model.textures.iter().map(|t| (t.label.as_str(), t.is_normal_map)).collect::<Vec<_>>(), [("default_diffuse", false), ("default_normal", true)]);
+    }
+
+    #[test]
+    fn vertex_colors_are_converted_to_linear() {
+        let colors = |model: crate::model::ModelData| {
+            let mut colors = model.meshes[0].vertices.iter().map(|v| v.color).collect::<Vec<_>>();
+            colors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            colors.dedup();
+            colors
+        };
+        let expected = [[0.0, 0.0, 0.0, 1.0], [0.21404114, 0.21404114, 0.21404114, 1.0], [1.0, 1.0, 1.0, 1.0]];
+        let obj = load("colors.obj", "v 0 0 0 0 0 0\nv 1 0 0 0.5 0.5 0.5\nv 1 1 0 1 1 1\nf 1 2 3\n").unwrap();
+        assert_eq!(colors(obj), expected);
+        let ply = load("colors.ply", "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0 0 0 0\n1 0 0 255 255 255\n1 1 0 255 255 255\n3 0 1 2\n").unwrap();
+        assert_eq!(colors(ply), [expected[0], expected[2]]);
+        // 没有顶点颜色是白色
+        let plain = load("no_colors.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n").unwrap();
+        assert_eq!(colors(plain), [[1.0; 4]]);
+    }
+}
@@ -0,0 +1,270 @@
+use cgmath::{Matrix4, One, Quaternion, SquareMatrix, Vector3};
+
+use crate::{instance::{Instance, InstanceRaw, InstanceStyle}, instance_manager::{InstanceHandle, InstanceManager}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    // 允许非等比缩放
+    pub scale: Vector3<f32>
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0)
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self { translation, ..Default::default() }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl From<&Instance> for Transform {
+    fn from(instance: &Instance) -> Self {
+        Self {
+            translation: instance.position,
+            rotation: instance.rotation,
+            scale: instance.scale
+        }
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    // 渲染场景时所用模型列表中的下标
+    pub model: Option<usize>,
+    style: InstanceStyle,
+    local: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: Matrix4<f32>,
+    // local 或祖先节点变化后需要重新计算 world
+    dirty: bool,
+    instance: Option<InstanceHandle>,
+    // world 或 style 变化后还没有同步到 InstanceManager
+    instance_dirty: bool
+}
+
+impl Node {
+    // 只有在 `Scene::update_world_matrices` 之后才是最新的
+    pub fn world_matrix(&self) -> Matrix4<f32> {
+        self.world
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(
+        &mut self,
+        name: &str,
+        parent: Option<NodeId>,
+        local: Transform,
+        model: Option<usize>
+    ) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            name: name.to_owned(),
+            model,
+            style: InstanceStyle::default(),
+            local,
+            parent,
+            children: Vec::new(),
+            world: Matrix4::identity(),
+            dirty: true,
+            instance: None,
+            instance_dirty: false
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(id);
+        }
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes.iter().enumerate().map(|(i, node)| (NodeId(i), node))
+    }
+
+    pub fn update_local<F: FnOnce(&mut Transform)>(&mut self, id: NodeId, f: F) {
+        let node = &mut self.nodes[id.0];
+        f(&mut node.local);
+        node.dirty = true;
+    }
+
+    // 外观变化不影响 world，只需要重新同步实例数据
+    pub fn update_style<F: FnOnce(&mut InstanceStyle)>(&mut self, id: NodeId, f: F) {
+        let node = &mut self.nodes[id.0];
+        f(&mut node.style);
+        node.instance_dirty = true;
+    }
+
+    // 重新计算有改动的节点及其子孙缓存的世界矩阵
+    pub fn update_world_matrices(&mut self) {
+        let roots = self.nodes.iter().enumerate()
+            .filter(|(_, n)| n.parent.is_none())
+            .map(|(i, _)| NodeId(i))
+            .collect::<Vec<_>>();
+        // 深度优先遍历，父节点变化时子节点也要重新计算
+        let mut stack = roots.into_iter()
+            .map(|id| (id, Matrix4::identity(), false))
+            .collect::<Vec<_>>();
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id.0];
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world = parent_world * node.local.matrix();
+                node.dirty = false;
+                node.instance_dirty = true;
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().map(|&c| (c, world, changed)));
+        }
+    }
+
+    pub fn node_by_instance(&self, instance: InstanceHandle) -> Option<(NodeId, &Node)> {
+        self.nodes().find(|(_, n)| n.instance == Some(instance))
+    }
+
+    // 把画 `model` 的节点中有变化的实例数据写入 `instances`
+    pub fn sync_instances(&mut self, model: usize, instances: &mut InstanceManager) {
+        for node in self.nodes.iter_mut().filter(|n| n.model == Some(model) && n.instance_dirty) {
+            let raw = InstanceRaw::new(node.world, &node.style);
+            match node.instance {
+                Some(handle) if instances.update(handle, raw) => {}
+                _ => node.instance = Some(instances.insert(raw))
+            }
+            node.instance_dirty = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Matrix4, Quaternion, Rotation3, Vector3};
+
+    use super::{Scene, Transform};
+    use crate::{instance::{InstanceRaw, InstanceStyle}, instance_manager::InstanceManager};
+
+    fn assert_matrix_eq(actual: Matrix4<f32>, expected: Matrix4<f32>) {
+        let (a, e): ([[f32; 4]; 4], [[f32; 4]; 4]) = (actual.into(), expected.into());
+        for (a, e) in a.iter().flatten().zip(e.iter().flatten()) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn child_world_is_parent_times_local() {
+        let mut scene = Scene::new();
+        let parent_local = Transform {
+            translation: Vector3::new(1.0, 2.0, 3.0),
+            rotation: Quaternion::from_angle_y(Deg(90.0)),
+            scale: Vector3::new(2.0, 1.0, 0.5)
+        };
+        let child_local = Transform::from_translation(Vector3::new(1.0, 0.0, 0.0));
+        let parent = scene.add_node("parent", None, parent_local, None);
+        let child = scene.add_node("child", Some(parent), child_local, None);
+        let grandchild = scene.add_node("grandchild", Some(child), Transform::from_translation(Vector3::new(0.0, 0.0, 4.0)), None);
+        scene.update_world_matrices();
+
+        assert_matrix_eq(scene.node(parent).world_matrix(), parent_local.matrix());
+        let child_world = parent_local.matrix() * child_local.matrix();
+        assert_matrix_eq(scene.node(child).world_matrix(), child_world);
+        // 父节点先缩放 x 再绕 y 转 90 度，(1, 0, 0) 落在 (1, 2, 1)
+        assert_matrix_eq(scene.node(child).world_matrix(), Matrix4::from_translation(Vector3::new(1.0, 2.0, 1.0))
+            * Matrix4::from(parent_local.rotation) * Matrix4::from_nonuniform_scale(2.0, 1.0, 0.5));
+        assert_matrix_eq(
+            scene.node(grandchild).world_matrix(),
+            child_world * Matrix4::from_translation(Vector3::new(0.0, 0.0, 4.0))
+        );
+        assert_eq!(scene.node(parent).children, [child]);
+    }
+
+    #[test]
+    fn dirty_nodes_update_their_descendants_only() {
+        let mut scene = Scene::new();
+        let root = scene.add_node("root", None, Transform::default(), Some(0));
+        let a = scene.add_node("a", Some(root), Transform::from_translation(Vector3::new(1.0, 0.0, 0.0)), Some(0));
+        let a_child = scene.add_node("a_child", Some(a), Transform::from_translation(Vector3::new(0.0, 1.0, 0.0)), Some(0));
+        let b = scene.add_node("b", Some(root), Transform::from_translation(Vector3::new(-1.0, 0.0, 0.0)), Some(0));
+        let mut instances = InstanceManager::new();
+        scene.update_world_matrices();
+        scene.sync_instances(0, &mut instances);
+        assert_eq!(instances.take_dirty(), Some(0..4));
+
+        // 什么都没变时不会重新同步
+        scene.update_world_matrices();
+        scene.sync_instances(0, &mut instances);
+        assert_eq!(instances.take_dirty(), None);
+
+        scene.update_local(a, |t| t.translation.z = 5.0);
+        scene.update_world_matrices();
+        assert_eq!(scene.node(a_child).world_matrix().w.truncate(), Vector3::new(1.0, 1.0, 5.0));
+        assert_eq!(scene.node(b).world_matrix().w.truncate(), Vector3::new(-1.0, 0.0, 0.0));
+        scene.sync_instances(0, &mut instances);
+        // a 和 a_child 的实例是 1 和 2
+        assert_eq!(instances.take_dirty(), Some(1..3));
+        assert_eq!(instances.instances()[2].model_matrix(), scene.node(a_child).world_matrix());
+
+        // 根节点变化时所有后代都要更新
+        scene.update_local(root, |t| t.translation.y = -1.0);
+        scene.update_world_matrices();
+        assert_eq!(scene.node(a_child).world_matrix().w.truncate(), Vector3::new(1.0, 0.0, 5.0));
+        assert_eq!(scene.node(b).world_matrix().w.truncate(), Vector3::new(-1.0, -1.0, 0.0));
+        scene.sync_instances(0, &mut instances);
+        assert_eq!(instances.take_dirty(), Some(0..4));
+    }
+
+    #[test]
+    fn node_by_instance_after_a_despawn() {
+        let mut scene = Scene::new();
+        let mut instances = InstanceManager::new();
+        // 运行时生成的实例不属于场景，排在场景节点前面
+        let spawned = instances.insert(InstanceRaw::new(Matrix4::from_scale(0.5), &InstanceStyle::default()));
+        let first = scene.add_node("first", None, Transform::default(), Some(0));
+        let last = scene.add_node("last", None, Transform::from_translation(Vector3::new(3.0, 0.0, 0.0)), Some(0));
+        scene.update_world_matrices();
+        scene.sync_instances(0, &mut instances);
+        assert_eq!(scene.node_by_instance(spawned).map(|(id, _)| id), None);
+        assert_eq!(scene.node_by_instance(instances.handle_at(2).unwrap()).map(|(id, _)| id), Some(last));
+
+        // 删除后最后一个实例搬到了 0，拾取到的下标仍要找到对应节点
+        assert!(instances.remove(spawned));
+        assert_eq!(scene.node_by_instance(instances.handle_at(0).unwrap()).map(|(id, _)| id), Some(last));
+        assert_eq!(scene.node_by_instance(instances.handle_at(1).unwrap()).map(|(id, _)| id), Some(first));
+        assert_eq!(scene.node_by_instance(spawned).map(|(id, _)| id), None);
+        assert_eq!(instances.instances()[0].model_matrix(), scene.node(last).world_matrix());
+
+        // 复用了被删除实例的槽，旧句柄依然找不到节点
+        let respawned = instances.insert(InstanceRaw::new(Matrix4::from_scale(0.5), &InstanceStyle::default()));
+        assert_ne!(respawned, spawned);
+        assert_eq!(scene.node_by_instance(spawned).map(|(id, _)| id), None);
+        assert_eq!(scene.node_by_instance(respawned).map(|(id, _)| id), None);
+    }
+}
@@ -1,25 +1,24 @@
 // vertex shader
-struct Camera{
-    view_pos: vec4f,
-    view_proj: mat4x4f
-};
-@group(1) @binding(0)
-var<uniform> camera: Camera;
-
-struct Light {
-    position: vec3f,
-    intensity: f32,
-    color: vec3f
-};
-@group(2) @binding(0)
-var<uniform> light: Light;
+#include "shaders/camera.wgsl"
+#include "shaders/light.wgsl"
 
 struct VertexInput {
     @location(0) position: vec3f,
     @location(1) tex_coords: vec2f,
     @location(2) normal: vec3f,
-    @location(3) tangent: vec3f,
-    @location(4) bitangent: vec3f
+    // w 是副切线的方向
+    @location(3) tangent: vec4f,
+    @location(4) color: vec4f
+};
+
+// 压缩的属性，见 vertex::PackedVertexAttributes
+struct PackedVertexInput {
+    @location(0) position: vec3f,
+    @location(1) tex_coords: vec2f,
+    // 八面体编码
+    @location(2) normal: vec2f,
+    @location(3) tangent: vec4f,
+    @location(4) color: vec4f
 };
 
 struct VertexOutput {
@@ -27,7 +26,10 @@ struct VertexOutput {
     @location(0) tex_coords: vec2f,
     @location(1) tangent_position: vec3f,
     @location(2) tangent_light_position: vec3f,
-    @location(3) tangent_view_position: vec3f
+    @location(3) tangent_view_position: vec3f,
+    @location(4) tint: vec4f,
+    @location(5) emissive: f32,
+    @location(6) color: vec4f
 };
 
 struct InstanceInput {
@@ -39,12 +41,42 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3f,
     @location(10) normal_matrix_1: vec3f,
     @location(11) normal_matrix_2: vec3f,
+
+    @location(12) tint: vec4f,
+    // location 13 是材质覆盖，由 CPU 按它选择材质
+    @location(14) emissive: f32,
 }
 
+// 与 vertex::octahedral_encode 对应
+fn octahedral_decode(e: vec2f) -> vec3f {
+    var n = vec3f(e, 1.0 - abs(e.x) - abs(e.y));
+    if (n.z < 0.0) {
+        n = vec3f((1.0 - abs(n.yx)) * select(vec2f(-1.0), vec2f(1.0), n.xy >= vec2f(0.0)), n.z);
+    }
+    return normalize(n);
+}
+
+// 顶点格式见 vertex::VertexLayout，每种格式一个入口
 @vertex
 fn vs_main(
     model: VertexInput,
     instance: InstanceInput
+) -> VertexOutput {
+    return transform_vertex(model, instance);
+}
+
+@vertex
+fn vs_main_packed(
+    model: PackedVertexInput,
+    instance: InstanceInput
+) -> VertexOutput {
+    let unpacked = VertexInput(model.position, model.tex_coords, octahedral_decode(model.normal), model.tangent, model.color);
+    return transform_vertex(unpacked, instance);
+}
+
+fn transform_vertex(
+    model: VertexInput,
+    instance: InstanceInput
 ) -> VertexOutput {
     let model_matrix = mat4x4f(
         instance.model_matrix_0,
@@ -58,9 +90,11 @@ fn vs_main(
         instance.normal_matrix_1,
         instance.normal_matrix_2,
     );
+    // 法线用逆转置矩阵变换，切线是表面上的方向，直接用模型矩阵变换
     let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
+    let world_tangent = normalize((model_matrix * vec4f(model.tangent.xyz, 0.0)).xyz);
+    // 与 MikkTSpace 一致，副切线由法线、切线和 w 重建
+    let world_bitangent = cross(world_normal, world_tangent) * model.tangent.w;
     let tangent_matrix = transpose(mat3x3f(
         world_tangent,
         world_bitangent,
@@ -74,6 +108,9 @@ fn vs_main(
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
+    out.tint = instance.tint;
+    out.emissive = instance.emissive;
+    out.color = model.color;
     return out;
 }
 
@@ -87,16 +124,36 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
+struct Material {
+    diffuse: vec4f,
+    emissive: vec3f,
+    alpha_cutoff: f32
+};
+@group(0) @binding(4)
+var<uniform> material: Material;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4f {
     // flip Y
     let uv = vec2f(in.tex_coords.x, 1. - in.tex_coords.y);
-    let object_color = textureSample(t_diffuse, s_diffuse, uv);
+    let params = material;
+    let object_color = textureSample(t_diffuse, s_diffuse, uv) * in.color * params.diffuse * in.tint;
+#ifdef NORMAL_MAP
+    // textureSample 必须在 discard 之前，保证控制流是 uniform 的
     let object_normal = textureSample(t_normal, s_normal, uv);
+    // 缩小过的 mip 里法线不是单位长度
+    let tangent_normal = normalize(object_normal.xyz * 2.0 - 1.0);
+#else
+    // 切线空间里的表面法线
+    let tangent_normal = vec3f(0.0, 0.0, 1.0);
+#endif
+    // 只有 Mask 材质的 alpha_cutoff 大于 0
+    if (object_color.a < params.alpha_cutoff) {
+        discard;
+    }
     let ambient_strength = 0.1;
     let ambient_color = light.color * ambient_strength;
 
-    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
     let light_dir = normalize(in.tangent_light_position - in.tangent_position);
     let view_dir = normalize(in.tangent_view_position - in.tangent_position);
     let half_dir = normalize(view_dir + light_dir);
@@ -107,6 +164,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4f {
     let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
     let specular_color = specular_strength * light.color * light.intensity;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.rgb;
+    let emissive_color = params.emissive + object_color.rgb * in.emissive;
+    let result = (ambient_color + diffuse_color + specular_color) * object_color.rgb + emissive_color;
     return vec4f(result, object_color.a);
 }
\ No newline at end of file
@@ -0,0 +1,7 @@
+// 与 camera::CameraUniform 一致，CAMERA_GROUP 由管线定义
+struct Camera {
+    view_pos: vec4f,
+    view_proj: mat4x4f
+}
+@group(CAMERA_GROUP) @binding(0)
+var<uniform> camera: Camera;
@@ -0,0 +1,8 @@
+// 与 light::PointLightUniform 一致，LIGHT_GROUP 由管线定义
+struct Light {
+    position: vec3f,
+    intensity: f32,
+    color: vec3f
+}
+@group(LIGHT_GROUP) @binding(0)
+var<uniform> light: Light;
@@ -0,0 +1,205 @@
+mod preprocessor;
+#[cfg(test)]
+pub mod reflect;
+
+pub use preprocessor::preprocess;
+
+// 编译进程序的 WGSL。开发模式下改为从源码目录读取，文件修改后重新加载
+pub struct ShaderLibrary {
+    shaders: Vec<ShaderFile>,
+    watch: bool,
+    last_poll: instant::Instant
+}
+
+struct ShaderFile {
+    // 相对 src/ 的路径
+    name: &'static str,
+    source: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    modified: Option<std::time::SystemTime>
+}
+
+impl ShaderFile {
+    // 源码没有变化时返回 None
+    fn replace(&mut self, source: String) -> Option<ShaderChange> {
+        if source == self.source {
+            return None;
+        }
+        Some(ShaderChange {
+            name: self.name,
+            previous: std::mem::replace(&mut self.source, source)
+        })
+    }
+}
+
+// 磁盘上源码有改动的着色器文件
+pub struct ShaderChange {
+    pub name: &'static str,
+    pub previous: String
+}
+
+// 环境变量，设置后开启着色器热加载
+const HOT_RELOAD_ENV: &str = "SHADER_HOT_RELOAD";
+const POLL_INTERVAL: instant::Duration = instant::Duration::from_millis(500);
+
+// 路径相对 src/
+macro_rules! embedded_shader {
+    ($name: literal) => {
+        ($name, include_str!(concat!("../", $name)))
+    };
+}
+
+const EMBEDDED: &[(&str, &str)] = &[
+    embedded_shader!("shader.wgsl"),
+    embedded_shader!("light/light.wgsl"),
+    embedded_shader!("shaders/camera.wgsl"),
+    embedded_shader!("shaders/light.wgsl"),
+    embedded_shader!("debug/debug.wgsl"),
+    embedded_shader!("picking/picking.wgsl")
+];
+
+impl ShaderLibrary {
+    // 设置了 `SHADER_HOT_RELOAD` 时监视源码目录，wasm 上从不监视
+    pub fn new() -> Self {
+        let watch = cfg!(not(target_arch = "wasm32")) && std::env::var_os(HOT_RELOAD_ENV).is_some();
+        let mut library = Self {
+            shaders: EMBEDDED.iter().map(|&(name, source)| ShaderFile {
+                name,
+                source: source.to_owned(),
+                #[cfg(not(target_arch = "wasm32"))]
+                modified: None
+            }).collect(),
+            watch,
+            last_poll: instant::Instant::now()
+        };
+        if watch {
+            log::info!("shader hot reload enabled");
+            // 源码目录里的版本可能比编译进来的新
+            library.reload_changed();
+        }
+        library
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.shaders.iter().find(|s| s.name == name).map(|s| s.source.as_str())
+    }
+
+    // 对 `name` 和它 include 的文件做预处理。开启热加载时还会验证结果，
+    // 改错了在创建管线之前就能报出来
+    pub fn compose(&self, name: &str, defines: &[(&str, &str)]) -> anyhow::Result<String> {
+        let source = preprocess(name, defines, |n| self.get(n))?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.watch {
+            validate(&source, name).map_err(anyhow::Error::msg)?;
+        }
+        Ok(source)
+    }
+
+    // 管线创建失败时恢复这次改动之前的源码
+    pub fn revert(&mut self, change: ShaderChange) {
+        if let Some(shader) = self.shaders.iter_mut().find(|s| s.name == change.name) {
+            shader.source = change.previous;
+        }
+    }
+
+    // 返回上次 poll 以来磁盘上有改动的文件
+    pub fn poll(&mut self) -> Vec<ShaderChange> {
+        if !self.watch || self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = instant::Instant::now();
+        self.reload_changed()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_changed(&mut self) -> Vec<ShaderChange> {
+        let mut changes = Vec::new();
+        for shader in &mut self.shaders {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src").join(shader.name);
+            let modified = match std::fs::metadata(&path).and_then(|m| m.modified()) {
+                Ok(modified) => modified,
+                Err(e) => {
+                    log::warn!("can't watch {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if shader.modified == Some(modified) {
+                continue;
+            }
+            shader.modified = Some(modified);
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) => {
+                    log::error!("failed to read {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            changes.extend(shader.replace(source));
+        }
+        changes
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn reload_changed(&mut self) -> Vec<ShaderChange> {
+        Vec::new()
+    }
+}
+
+// 用 naga 解析并验证 WGSL，返回格式化后的错误
+#[cfg(not(target_arch = "wasm32"))]
+pub fn validate(source: &str, path: &str) -> Result<(), String> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| e.emit_to_string_with_path(source, path))?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| e.emit_to_string_with_path(source, path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ShaderLibrary, EMBEDDED};
+
+    fn wgsl_files(dir: &std::path::Path, files: &mut Vec<String>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                wgsl_files(&path, files);
+            } else if path.extension().is_some_and(|e| e == "wgsl") {
+                let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+                files.push(path.strip_prefix(src).unwrap().to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+
+    // 没注册的文件改了也不会重新加载
+    #[test]
+    fn every_shader_is_embedded() {
+        let mut files = Vec::new();
+        wgsl_files(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut files);
+        for file in files {
+            assert!(EMBEDDED.iter().any(|&(name, _)| name == file), "{} is not in EMBEDDED", file);
+        }
+        let library = ShaderLibrary::new();
+        for (name, _) in EMBEDDED {
+            library.compose(name, &[("CAMERA_GROUP", "0"), ("LIGHT_GROUP", "1")]).unwrap();
+        }
+    }
+
+    #[test]
+    fn failed_reload_keeps_the_previous_source() {
+        let mut library = ShaderLibrary::new();
+        // 热加载时 compose 会验证结果
+        library.watch = true;
+        let previous = library.compose("debug/debug.wgsl", &[]).unwrap();
+        let shader = library.shaders.iter_mut().find(|s| s.name == "debug/debug.wgsl").unwrap();
+        assert!(shader.replace(shader.source.clone()).is_none());
+        let change = shader.replace("fn broken( -> {".to_owned()).unwrap();
+        assert_eq!(change.previous, previous);
+        let error = library.compose("debug/debug.wgsl", &[]).unwrap_err();
+        assert!(format!("{:#}", error).contains("debug/debug.wgsl"), "{:#}", error);
+
+        library.revert(change);
+        assert_eq!(library.compose("debug/debug.wgsl", &[]).unwrap(), previous);
+    }
+}
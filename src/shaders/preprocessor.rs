@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+
+// 一个很小的 WGSL 预处理器，支持以下指令，每条指令单独占一行：
+//   #include "path"      路径相对 src/，同一个文件只会被展开一次
+//   #define NAME [value] 之后出现的 NAME 标识符替换为 value
+//   #undef NAME
+//   #ifdef NAME / #ifndef NAME / #else / #endif
+// 相同的输入总是得到相同的输出。
+pub fn preprocess<'a>(
+    name: &str,
+    defines: &[(&str, &str)],
+    load: impl Fn(&str) -> Option<&'a str>
+) -> anyhow::Result<String> {
+    let mut state = State {
+        defines: defines.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect(),
+        included: Vec::new(),
+        stack: Vec::new(),
+        output: String::new()
+    };
+    state.file(name, &load)?;
+    Ok(state.output)
+}
+
+struct State {
+    defines: BTreeMap<String, String>,
+    // 已经展开过的文件
+    included: Vec<String>,
+    // 正在展开的文件，用来发现循环引用
+    stack: Vec<String>,
+    output: String
+}
+
+// 一层 #ifdef
+struct Branch {
+    // 外层是否在输出
+    parent_active: bool,
+    condition: bool,
+    in_else: bool
+}
+
+impl Branch {
+    fn active(&self) -> bool {
+        self.parent_active && (self.condition != self.in_else)
+    }
+}
+
+impl State {
+    fn file<'a>(&mut self, name: &str, load: &impl Fn(&str) -> Option<&'a str>) -> anyhow::Result<()> {
+        if self.stack.iter().any(|n| n == name) {
+            anyhow::bail!("circular #include: {} -> {}", self.stack.join(" -> "), name);
+        }
+        if self.included.iter().any(|n| n == name) {
+            return Ok(());
+        }
+        let source = match load(name) {
+            Some(source) => source,
+            None => anyhow::bail!("{}: file not found", name)
+        };
+        self.included.push(name.to_owned());
+        self.stack.push(name.to_owned());
+
+        let mut branches: Vec<Branch> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let at = |message: String| anyhow::anyhow!("{}:{}: {}", name, i + 1, message);
+            let active = branches.last().is_none_or(Branch::active);
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.line(line);
+                }
+                continue;
+            };
+            let mut words = directive.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let argument = words.next();
+            match (keyword, argument) {
+                ("ifdef" | "ifndef", Some(flag)) => branches.push(Branch {
+                    parent_active: active,
+                    condition: self.defines.contains_key(flag) == (keyword == "ifdef"),
+                    in_else: false
+                }),
+                ("else", None) => match branches.last_mut() {
+                    Some(branch) if !branch.in_else => branch.in_else = true,
+                    _ => return Err(at("#else without #ifdef".into()))
+                },
+                ("endif", None) => {
+                    if branches.pop().is_none() {
+                        return Err(at("#endif without #ifdef".into()));
+                    }
+                }
+                // 不输出的分支里只需要跟踪嵌套
+                _ if !active => {}
+                ("define", Some(flag)) => {
+                    let value = words.collect::<Vec<_>>().join(" ");
+                    self.defines.insert(flag.to_owned(), value);
+                }
+                ("undef", Some(flag)) => {
+                    self.defines.remove(flag);
+                }
+                ("include", Some(path)) => {
+                    let path = match path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) {
+                        Some(path) => path,
+                        None => return Err(at(format!("expected a quoted path, found {}", path)))
+                    };
+                    self.file(path, load).map_err(|e| at(e.to_string()))?;
+                }
+                _ => return Err(at(format!("invalid directive #{}", directive.trim())))
+            }
+        }
+        if !branches.is_empty() {
+            anyhow::bail!("{}: missing #endif", name);
+        }
+        self.stack.pop();
+        Ok(())
+    }
+
+    // 输出一行普通代码，替换其中定义过的标识符
+    fn line(&mut self, line: &str) {
+        let mut rest = line;
+        while let Some(start) = rest.find(is_ident_char) {
+            self.output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+            let ident = &rest[..end];
+            match self.defines.get(ident) {
+                Some(value) if !value.is_empty() => self.output.push_str(value),
+                _ => self.output.push_str(ident)
+            }
+            rest = &rest[end..];
+        }
+        self.output.push_str(rest);
+        self.output.push('\n');
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::preprocess;
+
+    fn files(name: &str) -> Option<&'static str> {
+        match name {
+            "camera.wgsl" => Some("struct Camera {\n    view_proj: mat4x4f\n}\n@group(CAMERA_GROUP) @binding(0)\nvar<uniform> camera: Camera;"),
+            "common.wgsl" => Some("#include \"camera.wgsl\"\nconst PI: f32 = 3.14159;"),
+            "a.wgsl" => Some("#include \"b.wgsl\""),
+            "b.wgsl" => Some("#include \"a.wgsl\""),
+            _ => None
+        }
+    }
+
+    fn run(source: &'static str, defines: &[(&str, &str)]) -> anyhow::Result<String> {
+        preprocess("main.wgsl", defines, |name| if name == "main.wgsl" { Some(source) } else { files(name) })
+    }
+
+    #[test]
+    fn plain_source_is_unchanged() {
+        let source = "@vertex\nfn vs_main() -> @builtin(position) vec4f {\n    return vec4f(0.0);\n}\n";
+        assert_eq!(run(source, &[]).unwrap(), source);
+    }
+
+    #[test]
+    fn include_substitutes_defines() {
+        let out = run("#include \"camera.wgsl\"\nfn f() {}", &[("CAMERA_GROUP", "1")]).unwrap();
+        assert!(out.starts_with("struct Camera {"));
+        assert!(out.contains("@group(1) @binding(0)"));
+        assert!(out.ends_with("fn f() {}\n"));
+    }
+
+    #[test]
+    fn include_only_once() {
+        let out = run("#include \"common.wgsl\"\n#include \"camera.wgsl\"", &[("CAMERA_GROUP", "0")]).unwrap();
+        assert_eq!(out.matches("struct Camera").count(), 1);
+        assert!(out.contains("const PI"));
+    }
+
+    #[test]
+    fn define_replaces_whole_identifiers_only() {
+        let out = run("#define SIZE 4u\nlet a = SIZE;\nlet b = SIZE_2;\nlet c = MY_SIZE;", &[]).unwrap();
+        assert_eq!(out, "let a = 4u;\nlet b = SIZE_2;\nlet c = MY_SIZE;\n");
+    }
+
+    #[test]
+    fn ifdef_selects_branches() {
+        let source = "#ifdef NORMAL_MAP\nmapped\n#else\nflat\n#endif\n#ifndef SHADOWS\nno shadows\n#endif";
+        assert_eq!(run(source, &[("NORMAL_MAP", "")]).unwrap(), "mapped\nno shadows\n");
+        assert_eq!(run(source, &[("SHADOWS", "")]).unwrap(), "flat\n");
+    }
+
+    #[test]
+    fn nested_ifdef_in_inactive_branch() {
+        let source = "#ifdef A\n#ifdef B\nab\n#else\na\n#endif\n#define C 1\n#endif\nC";
+        assert_eq!(run(source, &[("B", "")]).unwrap(), "C\n");
+        assert_eq!(run(source, &[("A", "")]).unwrap(), "a\n1\n");
+    }
+
+    #[test]
+    fn undef_removes_define() {
+        let out = run("#define X 1\nX\n#undef X\nX\n#ifdef X\nset\n#endif", &[]).unwrap();
+        assert_eq!(out, "1\nX\n");
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        let defines = [("CAMERA_GROUP", "2"), ("A", "1"), ("B", "2")];
+        let first = run("#include \"common.wgsl\"\nA B", &defines).unwrap();
+        for _ in 0..10 {
+            assert_eq!(run("#include \"common.wgsl\"\nA B", &defines).unwrap(), first);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_file_and_line() {
+        let missing = run("fn f() {}\n#include \"missing.wgsl\"", &[]).unwrap_err().to_string();
+        assert!(missing.starts_with("main.wgsl:2:"), "{}", missing);
+        assert!(missing.contains("missing.wgsl: file not found"), "{}", missing);
+
+        let unknown = run("#pragma once", &[]).unwrap_err().to_string();
+        assert_eq!(unknown, "main.wgsl:1: invalid directive #pragma once");
+    }
+
+    #[test]
+    fn unbalanced_conditionals_are_errors() {
+        assert!(run("#ifdef A\nx", &[]).is_err());
+        assert!(run("#endif", &[]).is_err());
+        assert!(run("#ifdef A\n#else\n#else\n#endif", &[]).is_err());
+    }
+
+    #[test]
+    fn circular_include_is_an_error() {
+        let error = run("#include \"a.wgsl\"", &[]).unwrap_err().to_string();
+        assert!(error.contains("circular #include"), "{}", error);
+    }
+}
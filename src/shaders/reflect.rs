@@ -0,0 +1,160 @@
+// 测试用：用 naga 解析 WGSL，检查 Rust 端的 uniform 结构体和顶点布局是否与着色器一致。
+// 不一致时 panic，让测试失败。
+use naga::{Binding, ScalarKind, TypeInner};
+
+pub struct ShaderReflection {
+    name: String,
+    module: naga::Module,
+    layouter: naga::proc::Layouter
+}
+
+impl ShaderReflection {
+    // 用和管线相同的宏预处理着色器库里的 `name`
+    pub fn compose(name: &str, defines: &[(&str, &str)]) -> Self {
+        let source = super::ShaderLibrary::new().compose(name, defines)
+            .unwrap_or_else(|e| panic!("failed to compose {}: {}", name, e));
+        Self::parse(name, &source)
+    }
+
+    pub fn parse(name: &str, source: &str) -> Self {
+        if let Err(e) = super::validate(source, name) {
+            panic!("{}", e);
+        }
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let mut layouter = naga::proc::Layouter::default();
+        layouter.update(module.to_ctx()).unwrap();
+        Self { name: name.to_owned(), module, layouter }
+    }
+
+    // 比较全局变量 `var` 的结构体和 `T`。`fields` 是 Rust 字段的偏移和大小，见 `gpu_fields!`。
+    // 两边以 `_` 开头的成员都是补齐
+    pub fn check_uniform<T>(&self, var: &str, fields: &[(usize, usize)]) {
+        let global = self.module.global_variables.iter()
+            .map(|(_, g)| g)
+            .find(|g| g.name.as_deref() == Some(var))
+            .unwrap_or_else(|| panic!("{} has no global named {}", self.name, var));
+        let rust_size = std::mem::size_of::<T>();
+        let wgsl_size = self.layouter[global.ty].size as usize;
+        assert_eq!(
+            wgsl_size, rust_size,
+            "{}: {} is {} bytes in WGSL but {} is {} bytes",
+            self.name, var, wgsl_size, std::any::type_name::<T>(), rust_size
+        );
+        let members: Vec<(String, usize, usize)> = match &self.module.types[global.ty].inner {
+            TypeInner::Struct { members, .. } => members.iter()
+                .filter(|m| !m.name.as_deref().unwrap_or("").starts_with('_'))
+                .map(|m| (m.name.clone().unwrap_or_default(), m.offset as usize, self.layouter[m.ty].size as usize))
+                .collect(),
+            _ => vec![(var.to_owned(), 0, wgsl_size)]
+        };
+        assert_eq!(
+            members.len(), fields.len(),
+            "{}: {} has {} members in WGSL but {} has {} fields",
+            self.name, var, members.len(), std::any::type_name::<T>(), fields.len()
+        );
+        for ((member, wgsl_offset, wgsl_size), &(rust_offset, rust_size)) in members.iter().zip(fields) {
+            assert_eq!(
+                (*wgsl_offset, *wgsl_size), (rust_offset, rust_size),
+                "{}: {}.{} is at offset {} with size {} in WGSL, but the Rust field is at offset {} with size {}",
+                self.name, var, member, wgsl_offset, wgsl_size, rust_offset, rust_size
+            );
+        }
+    }
+
+    // 检查顶点入口读取的每个 location 都由 `buffers` 以相同的格式提供，
+    // 并且每个缓冲的属性紧密排列，没有空隙和重叠
+    pub fn check_vertex_input(&self, entry: &str, buffers: &[wgpu::VertexBufferLayout]) {
+        let entry_point = self.module.entry_points.iter()
+            .find(|e| e.name == entry && e.stage == naga::ShaderStage::Vertex)
+            .unwrap_or_else(|| panic!("{} has no vertex entry point {}", self.name, entry));
+
+        let mut inputs = Vec::new();
+        for argument in &entry_point.function.arguments {
+            match (&argument.binding, &self.module.types[argument.ty].inner) {
+                (Some(Binding::Location { location, .. }), inner) => inputs.push((*location, inner)),
+                (None, TypeInner::Struct { members, .. }) => {
+                    for member in members {
+                        if let Some(Binding::Location { location, .. }) = member.binding {
+                            inputs.push((location, &self.module.types[member.ty].inner));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (location, inner) in inputs {
+            let attribute = buffers.iter()
+                .flat_map(|b| b.attributes)
+                .find(|a| a.shader_location == location)
+                .unwrap_or_else(|| panic!("{}: {} reads location {} but no vertex buffer provides it", self.name, entry, location));
+            assert_eq!(
+                shader_input(inner), vertex_format_input(attribute.format),
+                "{}: {} location {} is {:?} in WGSL but {:?} in the vertex layout",
+                self.name, entry, location, inner, attribute.format
+            );
+        }
+
+        let mut locations: Vec<u32> = buffers.iter().flat_map(|b| b.attributes).map(|a| a.shader_location).collect();
+        locations.sort();
+        let count = locations.len();
+        locations.dedup();
+        assert_eq!(locations.len(), count, "{}: vertex layouts for {} reuse a location", self.name, entry);
+
+        for (i, buffer) in buffers.iter().enumerate() {
+            let mut attributes = buffer.attributes.to_vec();
+            attributes.sort_by_key(|a| a.offset);
+            let mut end = 0;
+            for attribute in attributes {
+                assert_eq!(
+                    attribute.offset, end,
+                    "{}: location {} in vertex buffer {} of {} should start at offset {}",
+                    self.name, attribute.shader_location, i, entry, end
+                );
+                end = attribute.offset + attribute.format.size();
+            }
+            assert_eq!(end, buffer.array_stride, "{}: vertex buffer {} of {} doesn't cover its stride", self.name, i, entry);
+        }
+    }
+}
+
+// 分量个数和类型
+fn shader_input(inner: &TypeInner) -> (u32, ScalarKind) {
+    match *inner {
+        TypeInner::Scalar { kind, width: 4 } => (1, kind),
+        TypeInner::Vector { size, kind, width: 4 } => (size as u32, kind),
+        _ => panic!("unsupported vertex input {:?}", inner)
+    }
+}
+
+// 归一化和半精度格式在着色器里读到的是 f32
+fn vertex_format_input(format: wgpu::VertexFormat) -> (u32, ScalarKind) {
+    use wgpu::VertexFormat::*;
+    match format {
+        Float32 => (1, ScalarKind::Float),
+        Float32x2 | Float16x2 | Unorm8x2 | Snorm8x2 | Unorm16x2 | Snorm16x2 => (2, ScalarKind::Float),
+        Float32x3 => (3, ScalarKind::Float),
+        Float32x4 | Float16x4 | Unorm8x4 | Snorm8x4 | Unorm16x4 | Snorm16x4 => (4, ScalarKind::Float),
+        Uint32 => (1, ScalarKind::Uint),
+        Uint32x2 | Uint8x2 | Uint16x2 => (2, ScalarKind::Uint),
+        Uint32x3 => (3, ScalarKind::Uint),
+        Uint32x4 | Uint8x4 | Uint16x4 => (4, ScalarKind::Uint),
+        Sint32 => (1, ScalarKind::Sint),
+        Sint32x2 | Sint8x2 | Sint16x2 => (2, ScalarKind::Sint),
+        Sint32x3 => (3, ScalarKind::Sint),
+        Sint32x4 | Sint8x4 | Sint16x4 => (4, ScalarKind::Sint),
+        _ => panic!("unsupported vertex format {:?}", format)
+    }
+}
+
+pub fn field_size<T, F>(_: impl Fn(&T) -> &F) -> usize {
+    std::mem::size_of::<F>()
+}
+
+// 结构体字段的偏移和大小，用于 ShaderReflection::check_uniform
+macro_rules! gpu_fields {
+    ($t: ty: $($field: ident),*) => {
+        [$((std::mem::offset_of!($t, $field), $crate::shaders::reflect::field_size(|v: &$t| &v.$field))),*]
+    };
+}
+pub(crate) use gpu_fields;
@@ -1,45 +1,26 @@
-use anyhow::*;
 use image::GenericImageView;
 
 pub struct Texture {
-    pub texture: wgpu::Texture,
+    // view 和 sampler 用到的纹理，只是持有它
+    _texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
 }
 
 impl Texture {
-    pub fn from_bytes(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        bytes: &[u8],
-        label: &str,
-        is_normal_map: bool
-    ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
-    }
-    pub fn from_image(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        img: &image::DynamicImage,
-        label: Option<&str>,
-        is_normal_map: bool
-    ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
-
+    pub fn from_data(device: &wgpu::Device, queue: &wgpu::Queue, data: &TextureData) -> Self {
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
+            width: data.width,
+            height: data.height,
             depth_or_array_layers: 1,
         };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label,
+            label: Some(&data.label),
             size,
-            mip_level_count: 1,
+            mip_level_count: data.mips.len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: if is_normal_map {
+            format: if data.is_normal_map {
                 wgpu::TextureFormat::Rgba8Unorm
             } else {
                 wgpu::TextureFormat::Rgba8UnormSrgb
@@ -47,21 +28,24 @@ impl Texture {
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
+        for (level, rgba) in data.mips.iter().enumerate() {
+            let (width, height) = data.mip_size(level);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                rgba,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -74,11 +58,11 @@ impl Texture {
             ..Default::default()
         });
 
-        Ok(Self {
-            texture,
+        Self {
+            _texture: texture,
             view,
             sampler,
-        })
+        }
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
@@ -86,6 +70,7 @@ impl Texture {
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -97,7 +82,7 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -118,6 +103,52 @@ impl Texture {
             ..Default::default()
         });
 
-        Self { texture, view, sampler }
+        Self { _texture: texture, view, sampler }
+    }
+}
+
+// 解码好的 RGBA8 贴图和完整的 mipmap 链，可以直接上传，也可以烘焙到文件里
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureData {
+    pub label: String,
+    pub width: u32,
+    pub height: u32,
+    pub is_normal_map: bool,
+    // 第 0 级是原图，之后每级宽高减半，直到 1x1
+    pub mips: Vec<Vec<u8>>
+}
+
+impl TextureData {
+    pub fn from_image(img: &image::DynamicImage, label: &str, is_normal_map: bool) -> Self {
+        let (width, height) = img.dimensions();
+        let mut mips = vec![img.to_rgba8()];
+        while let Some(last) = mips.last().filter(|m| m.width() > 1 || m.height() > 1) {
+            // 法线贴图缩小后不再是单位长度，着色器里会重新 normalize
+            let next = image::imageops::resize(
+                last,
+                (last.width() / 2).max(1),
+                (last.height() / 2).max(1),
+                image::imageops::FilterType::Triangle
+            );
+            mips.push(next);
+        }
+        Self {
+            label: label.to_owned(),
+            width,
+            height,
+            is_normal_map,
+            mips: mips.into_iter().map(|m| m.into_raw()).collect()
+        }
+    }
+
+    // 1x1 的纯色贴图
+    pub fn solid(label: &str, rgba: [u8; 4], is_normal_map: bool) -> Self {
+        Self { label: label.to_owned(), width: 1, height: 1, is_normal_map, mips: vec![rgba.to_vec()] }
+    }
+
+    pub fn mip_size(&self, level: usize) -> (u32, u32) {
+        // 超过 31 级时移位会溢出，这时只剩 1 个像素
+        let shift = |size: u32| u32::try_from(level).ok().and_then(|l| size.checked_shr(l)).unwrap_or(0).max(1);
+        (shift(self.width), shift(self.height))
     }
 }
@@ -1,3 +1,209 @@
+// 模型顶点在 GPU 上分成两个流：位置单独一个缓冲，其余属性一个缓冲。
+// 只用位置的管线（光源、拾取、线框）只绑定位置流。绘制模型时槽 0 是位置，
+// 槽 1 是实例，槽 2 是属性。CPU 端始终是 model::ModelVertex
+use crate::model::ModelVertex;
+
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
-}
\ No newline at end of file
+}
+
+// 选择压缩格式的环境变量，值为 full 或 packed
+const VERTEX_LAYOUT_ENV: &str = "LEARN_WGPU_VERTEX_LAYOUT";
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PositionVertex {
+    pub position: [f32; 3]
+}
+
+impl PositionVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+}
+
+impl Vertex for PositionVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PositionVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS
+        }
+    }
+}
+
+// 全精度的属性，和 ModelVertex 一样
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VertexAttributes {
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    pub color: [f32; 4]
+}
+
+impl VertexAttributes {
+    const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![1 => Float32x2, 2 => Float32x3, 3 => Float32x4, 4 => Float32x4];
+}
+
+impl Vertex for VertexAttributes {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<VertexAttributes>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS
+        }
+    }
+}
+
+// 压缩的属性，52 字节变成 16 字节：
+// 半精度纹理坐标，八面体编码的法线，8 位的切线（w 是副切线方向）和线性颜色
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PackedVertexAttributes {
+    pub tex_coords: [half::f16; 2],
+    pub normal: [i16; 2],
+    pub tangent: [i8; 4],
+    pub color: [u8; 4]
+}
+
+impl PackedVertexAttributes {
+    const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![1 => Float16x2, 2 => Snorm16x2, 3 => Snorm8x4, 4 => Unorm8x4];
+}
+
+impl Vertex for PackedVertexAttributes {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PackedVertexAttributes>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS
+        }
+    }
+}
+
+impl From<&ModelVertex> for PackedVertexAttributes {
+    fn from(v: &ModelVertex) -> Self {
+        Self {
+            tex_coords: v.tex_coords.map(half::f16::from_f32),
+            normal: octahedral_encode(v.normal).map(|c| (c * i16::MAX as f32).round() as i16),
+            tangent: v.tangent.map(|c| (c.clamp(-1.0, 1.0) * i8::MAX as f32).round() as i8),
+            color: v.color.map(|c| (c.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+        }
+    }
+}
+
+// 属性流的格式。同一个模型的所有网格用同一种，管线按它选择顶点入口和 desc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VertexLayout {
+    Full,
+    Packed
+}
+
+impl VertexLayout {
+    // 默认压缩，`LEARN_WGPU_VERTEX_LAYOUT=full` 时用全精度
+    pub fn from_env() -> Self {
+        match std::env::var(VERTEX_LAYOUT_ENV).as_deref() {
+            Ok("full") => VertexLayout::Full,
+            Ok("packed") | Err(_) => VertexLayout::Packed,
+            Ok(other) => {
+                log::warn!("unknown {} {:?}, using packed vertices", VERTEX_LAYOUT_ENV, other);
+                VertexLayout::Packed
+            }
+        }
+    }
+
+    pub fn desc<'a>(self) -> wgpu::VertexBufferLayout<'a> {
+        match self {
+            VertexLayout::Full => VertexAttributes::desc(),
+            VertexLayout::Packed => PackedVertexAttributes::desc()
+        }
+    }
+
+    // 每个顶点的属性占多少字节
+    pub fn stride(self) -> usize {
+        self.desc().array_stride as usize
+    }
+
+    pub fn encode(self, vertices: &[ModelVertex]) -> Vec<u8> {
+        match self {
+            VertexLayout::Full => {
+                let attributes = vertices.iter().map(|v| VertexAttributes {
+                    tex_coords: v.tex_coords,
+                    normal: v.normal,
+                    tangent: v.tangent,
+                    color: v.color
+                }).collect::<Vec<_>>();
+                bytemuck::cast_slice(&attributes).to_vec()
+            }
+            VertexLayout::Packed => {
+                let attributes = vertices.iter().map(PackedVertexAttributes::from).collect::<Vec<_>>();
+                bytemuck::cast_slice(&attributes).to_vec()
+            }
+        }
+    }
+}
+
+pub fn positions(vertices: &[ModelVertex]) -> Vec<PositionVertex> {
+    vertices.iter().map(|v| PositionVertex { position: v.position }).collect()
+}
+
+// 单位向量投影到八面体再展开到 [-1, 1]²，解码见 shader.wgsl 的 octahedral_decode
+fn octahedral_encode([x, y, z]: [f32; 3]) -> [f32; 2] {
+    let length = x.abs() + y.abs() + z.abs();
+    if length == 0.0 {
+        return [0.0; 2];
+    }
+    let (u, v) = (x / length, y / length);
+    if z >= 0.0 {
+        [u, v]
+    } else {
+        // 下半球折到外面的四个角上
+        [(1.0 - v.abs()) * u.signum(), (1.0 - u.abs()) * v.signum()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::{octahedral_encode, PackedVertexAttributes, VertexAttributes, VertexLayout};
+    use crate::model::ModelVertex;
+
+    // 与 WGSL 里的解码相同
+    fn octahedral_decode([u, v]: [f32; 2]) -> cgmath::Vector3<f32> {
+        let z = 1.0 - u.abs() - v.abs();
+        let (x, y) = if z < 0.0 {
+            ((1.0 - v.abs()) * u.signum(), (1.0 - u.abs()) * v.signum())
+        } else {
+            (u, v)
+        };
+        cgmath::Vector3::new(x, y, z).normalize()
+    }
+
+    #[test]
+    fn packed_normals_survive_the_round_trip() {
+        for normal in [[0.0, 0.0, 1.0], [0.0, 0.0, -1.0], [1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.3, -0.5, -0.81], [-0.6, 0.64, 0.48]] {
+            let normal = cgmath::Vector3::from(normal).normalize();
+            let packed = PackedVertexAttributes::from(&ModelVertex { normal: normal.into(), ..Default::default() });
+            let decoded = octahedral_decode(packed.normal.map(|c| c as f32 / i16::MAX as f32));
+            assert!(decoded.dot(normal) > 0.99999, "{:?} decoded as {:?}", normal, decoded);
+        }
+        assert_eq!(octahedral_encode([0.0; 3]), [0.0; 2]);
+    }
+
+    #[test]
+    fn packed_vertices_are_smaller() {
+        let vertex = ModelVertex {
+            position: [1.0, 2.0, 3.0],
+            tex_coords: [0.25, 2.5],
+            normal: [0.0, 1.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, -1.0],
+            color: [1.0, 0.5, 0.0, 1.0]
+        };
+        assert_eq!(VertexLayout::Full.stride(), std::mem::size_of::<VertexAttributes>());
+        assert_eq!(VertexLayout::Full.encode(&[vertex; 2]).len(), 2 * 52);
+        assert_eq!(VertexLayout::Packed.encode(&[vertex; 2]).len(), 2 * 16);
+        let packed = PackedVertexAttributes::from(&vertex);
+        assert_eq!(packed.tex_coords.map(f32::from), [0.25, 2.5]);
+        assert_eq!(packed.tangent, [127, 0, 0, -127]);
+        assert_eq!(packed.color, [255, 128, 0, 255]);
+    }
+}
@@ -0,0 +1,298 @@
+use std::{borrow::Cow, collections::HashMap, sync::{Arc, RwLock}};
+
+use crate::{error::{Error, Result}, pack::Pack};
+
+// 命令行参数，可以给多次：`--res <目录或 .pack 文件>`
+const ROOT_ARG: &str = "--res";
+// 环境变量，按平台的路径分隔符给出多个目录或 .pack 文件
+const ROOT_ENV: &str = "LEARN_WGPU_RES";
+
+// 一个资源根：磁盘目录、URL 前缀、资源包或者内存里的文件
+pub enum Root {
+    #[cfg(not(target_arch = "wasm32"))]
+    Dir(std::path::PathBuf),
+    #[cfg(target_arch = "wasm32")]
+    Url(reqwest::Url),
+    Pack(Pack),
+    Memory(HashMap<String, Cow<'static, [u8]>>)
+}
+
+impl Root {
+    // `.pack` 结尾的路径作为资源包打开，其他的作为目录
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_path(path: std::path::PathBuf) -> Result<Self> {
+        if path.extension().is_some_and(|ext| ext == "pack") {
+            Ok(Root::Pack(Pack::open(&path)?))
+        } else {
+            Ok(Root::Dir(path))
+        }
+    }
+
+    // 用 embed-res feature 构建时打包进程序的 res 目录
+    pub fn embedded() -> Option<Self> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "embed-res")] {
+                let files = include!(concat!(env!("OUT_DIR"), "/embedded_res.rs"));
+                Some(Root::Memory(files.iter().map(|&(name, data)| (name.to_owned(), Cow::Borrowed(data))).collect()))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Root::Dir(dir) => dir.display().to_string(),
+            #[cfg(target_arch = "wasm32")]
+            Root::Url(url) => url.to_string(),
+            Root::Pack(pack) => format!("{} ({} files)", pack.name(), pack.file_count()),
+            Root::Memory(files) => format!("<{} files in memory>", files.len())
+        }
+    }
+
+    // 文件不在这个根里时返回 None，存在但读取失败时返回错误
+    async fn read(&self, file_name: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Root::Dir(dir) => {
+                let Some(path) = dir_path(dir, file_name) else {
+                    return Ok(None);
+                };
+                match std::fs::read(path) {
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(source) => Err(Error::Io { file: file_name.to_owned(), root: self.describe(), source })
+                }
+            }
+            #[cfg(target_arch = "wasm32")]
+            Root::Url(base) => {
+                let Ok(url) = base.join(file_name) else {
+                    return Ok(None);
+                };
+                let error = |source| Error::Http { file: file_name.to_owned(), root: self.describe(), source };
+                let response = reqwest::get(url).await.map_err(error)?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let data = response.error_for_status().map_err(error)?.bytes().await.map_err(error)?;
+                Ok(Some(data.to_vec()))
+            }
+            Root::Pack(pack) => Ok(pack.get(file_name).map(<[u8]>::to_vec)),
+            Root::Memory(files) => Ok(files.get(file_name).map(|data| data.to_vec()))
+        }
+    }
+}
+
+// 目录根里的文件路径。绝对路径和 `..` 会跑到目录外面，返回 None
+#[cfg(not(target_arch = "wasm32"))]
+fn dir_path(dir: &std::path::Path, file_name: &str) -> Option<std::path::PathBuf> {
+    use std::path::Component;
+    let path = std::path::Path::new(file_name);
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then(|| dir.join(path))
+}
+
+// 按顺序查找的资源根，前面的覆盖后面的
+pub struct Vfs {
+    roots: Vec<Root>
+}
+
+impl Vfs {
+    pub fn new(roots: Vec<Root>) -> Self {
+        Self { roots }
+    }
+
+    // 本地的顺序：`--res` 参数、`LEARN_WGPU_RES`、可执行文件旁边的 `res` 和 `res.pack`，
+    // 然后是构建目录里的副本，这样 `cargo run` 不用配置就能运行。开启资源热加载时源码里的
+    // `res` 目录排在最前面。Web 上是构建时的 `RES_PATH`（默认 `res`），相对于页面的 origin，
+    // `fetch_pack` 找到了 `res.pack` 时排在它后面。编译进程序的文件总是最后
+    pub fn from_env() -> Self {
+        let mut roots = Vec::new();
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                match reqwest::Url::parse(&format!("{}/", res_url())) {
+                    Ok(url) => roots.push(Root::Url(url)),
+                    Err(e) => log::error!("invalid resource URL {}: {}", res_url(), e)
+                }
+            } else {
+                use std::path::PathBuf;
+                if crate::resources::hot_reload() {
+                    roots.push(Root::Dir(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res")));
+                }
+                let mut paths = Vec::new();
+                let mut args = std::env::args_os().skip(1);
+                while let Some(arg) = args.next() {
+                    if arg == ROOT_ARG {
+                        paths.extend(args.next().map(PathBuf::from));
+                    }
+                }
+                if let Some(dirs) = std::env::var_os(ROOT_ENV) {
+                    paths.extend(std::env::split_paths(&dirs));
+                }
+                for path in paths {
+                    match Root::from_path(path) {
+                        Ok(root) => roots.push(root),
+                        Err(e) => log::error!("{}", e.report())
+                    }
+                }
+                if let Some(exe_dir) = std::env::current_exe().ok().as_ref().and_then(|exe| exe.parent()) {
+                    roots.push(Root::Dir(exe_dir.join("res")));
+                    // 和指定的包不同，默认位置没有包是正常的
+                    let pack = exe_dir.join("res.pack");
+                    if pack.is_file() {
+                        match Pack::open(&pack) {
+                            Ok(pack) => roots.push(Root::Pack(pack)),
+                            Err(e) => log::error!("{}", e.report())
+                        }
+                    }
+                }
+                roots.push(Root::Dir(PathBuf::from(env!("OUT_DIR")).join("res")));
+            }
+        }
+        roots.extend(Root::embedded());
+        Self::new(roots)
+    }
+
+    pub async fn read(&self, file_name: &str) -> Result<Vec<u8>> {
+        for root in &self.roots {
+            if let Some(data) = root.read(file_name).await? {
+                return Ok(data);
+            }
+        }
+        let roots = self.roots.iter().map(Root::describe).collect::<Vec<_>>();
+        Err(Error::NotFound { file: file_name.to_owned(), roots: roots.join(", ") })
+    }
+
+    // 找到文件的那个根里的修改时间，包和内存里的文件没有
+    pub fn modified(&self, file_name: &str) -> Option<std::time::SystemTime> {
+        for root in &self.roots {
+            match root {
+                #[cfg(not(target_arch = "wasm32"))]
+                Root::Dir(dir) => {
+                    if let Ok(metadata) = std::fs::metadata(dir_path(dir, file_name)?) {
+                        return metadata.modified().ok();
+                    }
+                }
+                #[cfg(target_arch = "wasm32")]
+                Root::Url(_) => return None,
+                Root::Pack(pack) => {
+                    if pack.get(file_name).is_some() {
+                        return None;
+                    }
+                }
+                Root::Memory(files) => {
+                    if files.contains_key(file_name) {
+                        return None;
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+// 页面上资源目录的 URL，不带结尾的 /
+#[cfg(target_arch = "wasm32")]
+fn res_url() -> String {
+    let origin = web_sys::window().and_then(|w| w.location().origin().ok()).unwrap_or_default();
+    format!("{}/{}", origin, option_env!("RES_PATH").unwrap_or("res"))
+}
+
+// Web 上一次下载整个 `res.pack`，有的话优先从包里读，没有时逐个下载文件
+#[cfg(target_arch = "wasm32")]
+pub async fn fetch_pack() {
+    let url = match reqwest::Url::parse(&format!("{}.pack", res_url())) {
+        Ok(url) => url,
+        Err(e) => return log::error!("{}", e)
+    };
+    match Pack::fetch(url).await {
+        Ok(Some(pack)) => {
+            let mut vfs = Vfs::from_env();
+            vfs.roots.insert(0, Root::Pack(pack));
+            set(vfs);
+        }
+        Ok(None) => {}
+        Err(e) => log::error!("{}", e.report())
+    }
+}
+
+// 后台加载线程也要用，所以是全局的。第一次使用时按 from_env 初始化
+static VFS: RwLock<Option<Arc<Vfs>>> = RwLock::new(None);
+
+pub fn get() -> Arc<Vfs> {
+    if let Some(vfs) = VFS.read().unwrap().as_ref() {
+        return vfs.clone();
+    }
+    VFS.write().unwrap().get_or_insert_with(|| {
+        let vfs = Vfs::from_env();
+        log::info!("resource roots: [{}]", vfs.roots.iter().map(Root::describe).collect::<Vec<_>>().join(", "));
+        Arc::new(vfs)
+    }).clone()
+}
+
+// 替换之后所有加载使用的资源根，已经在进行的加载继续用原来的
+pub fn set(vfs: Vfs) {
+    *VFS.write().unwrap() = Some(Arc::new(vfs));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, collections::HashMap};
+
+    use super::{Error, Root, Vfs};
+
+    fn memory(files: &[(&str, &'static [u8])]) -> Root {
+        Root::Memory(files.iter().map(|&(name, data)| (name.to_owned(), Cow::Borrowed(data))).collect::<HashMap<_, _>>())
+    }
+
+    #[test]
+    fn earlier_roots_win() {
+        let vfs = Vfs::new(vec![memory(&[("a.txt", b"first")]), memory(&[("a.txt", b"second"), ("b.txt", b"b")])]);
+        assert_eq!(pollster::block_on(vfs.read("a.txt")).unwrap(), b"first");
+        assert_eq!(pollster::block_on(vfs.read("b.txt")).unwrap(), b"b");
+        let missing = pollster::block_on(vfs.read("c.txt")).unwrap_err();
+        assert!(matches!(&missing, Error::NotFound { file, .. } if file == "c.txt"), "{}", missing);
+        assert!(missing.to_string().contains("2 files in memory"), "{}", missing);
+    }
+
+    #[test]
+    fn missing_directories_are_skipped() {
+        let out = std::path::Path::new(env!("OUT_DIR"));
+        let vfs = Vfs::new(vec![Root::Dir(out.join("no-such-dir")), Root::Dir(out.join("res"))]);
+        assert!(pollster::block_on(vfs.read("cube.obj")).is_ok());
+        assert!(vfs.modified("cube.obj").is_some());
+        assert!(vfs.modified("missing.obj").is_none());
+    }
+
+    #[test]
+    fn packs_are_roots() {
+        let pack = crate::pack::Pack::from_bytes("test", crate::pack::write(&[("a.txt".to_owned(), b"packed".to_vec())])).unwrap();
+        let vfs = Vfs::new(vec![Root::Pack(pack), memory(&[("a.txt", b"loose"), ("b.txt", b"b")])]);
+        assert_eq!(pollster::block_on(vfs.read("a.txt")).unwrap(), b"packed");
+        assert_eq!(pollster::block_on(vfs.read("b.txt")).unwrap(), b"b");
+        assert!(Root::from_path(std::path::PathBuf::from("missing.pack")).is_err());
+    }
+
+    #[test]
+    fn directories_only_serve_files_inside_them() {
+        let res = std::path::Path::new(env!("OUT_DIR")).join("res");
+        let vfs = Vfs::new(vec![Root::Dir(res.clone())]);
+        assert!(pollster::block_on(vfs.read("./cube.obj")).is_ok());
+        let absolute = res.join("cube.obj");
+        assert!(absolute.is_absolute() && absolute.is_file());
+        for name in [absolute.to_str().unwrap(), "../res/cube.obj", "sub/../../res/cube.obj"] {
+            let e = pollster::block_on(vfs.read(name)).unwrap_err();
+            assert!(matches!(e, Error::NotFound { .. }), "{}: {}", name, e);
+            assert!(vfs.modified(name).is_none(), "{}", name);
+        }
+    }
+
+    #[cfg(feature = "embed-res")]
+    #[test]
+    fn embedded_res_contains_the_models() {
+        let vfs = Vfs::new(vec![Root::embedded().unwrap()]);
+        assert!(pollster::block_on(vfs.read("cube.obj")).is_ok_and(|data| !data.is_empty()));
+    }
+}